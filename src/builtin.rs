@@ -1,6 +1,6 @@
 use crate::{
-    c_int, c_value, Block, BuiltinMap, CExp, CExpPtr, CompileFunc, DataKind, EvalEnv, Expr, Rc,
-    Value,
+    c_float, c_int, c_value, compress, hash, json, util, Block, BuiltinMap, CExp, CExpPtr,
+    CompileFunc, DataKind, EvalEnv, Expr, ObjRef, Rc, Value,
 };
 
 /// Add builtin functions to specified [BuiltinMap].
@@ -41,20 +41,160 @@ pub fn standard_builtins(map: &mut BuiltinMap) {
             DataKind::String,
             CompileFunc::Value(c_exception),
         ),
+        (
+            "ERRORMESSAGE",
+            DataKind::String,
+            CompileFunc::Value(c_error_message),
+        ),
+        ("ERRORLINE", DataKind::Int, CompileFunc::Int(c_error_line)),
+        (
+            "ERRORNUMBER",
+            DataKind::Int,
+            CompileFunc::Int(c_error_number),
+        ),
         ("LASTID", DataKind::Int, CompileFunc::Int(c_lastid)),
         ("ALLOCPAGE", DataKind::Int, CompileFunc::Int(c_allocpage)),
         #[cfg(feature = "pack")]
         ("REPACKFILE", DataKind::Int, CompileFunc::Int(c_repackfile)),
+        #[cfg(feature = "pack")]
+        ("REPACKDB", DataKind::Int, CompileFunc::Int(c_repackdb)),
+        ("BUILDINDEX", DataKind::Int, CompileFunc::Int(c_buildindex)),
         #[cfg(feature = "verify")]
         ("VERIFYDB", DataKind::String, CompileFunc::Value(c_verifydb)),
         #[cfg(feature = "renumber")]
         ("RENUMBER", DataKind::Int, CompileFunc::Int(c_renumber)),
         ("BINTOSTR", DataKind::String, CompileFunc::Value(c_bintostr)),
+        ("ABS", DataKind::Int, CompileFunc::Int(c_abs)),
+        ("FABS", DataKind::Float, CompileFunc::Float(c_fabs)),
+        ("ROUND", DataKind::Float, CompileFunc::Float(c_round)),
+        ("FLOOR", DataKind::Float, CompileFunc::Float(c_floor)),
+        ("CEILING", DataKind::Float, CompileFunc::Float(c_ceiling)),
+        ("POWER", DataKind::Float, CompileFunc::Float(c_power)),
+        ("SQRT", DataKind::Float, CompileFunc::Float(c_sqrt)),
+        ("LOG", DataKind::Float, CompileFunc::Float(c_log)),
+        ("LOG10", DataKind::Float, CompileFunc::Float(c_log10)),
+        ("EXP", DataKind::Float, CompileFunc::Float(c_exp)),
+        ("SIN", DataKind::Float, CompileFunc::Float(c_sin)),
+        ("COS", DataKind::Float, CompileFunc::Float(c_cos)),
+        ("TAN", DataKind::Float, CompileFunc::Float(c_tan)),
+        ("ASIN", DataKind::Float, CompileFunc::Float(c_asin)),
+        ("ACOS", DataKind::Float, CompileFunc::Float(c_acos)),
+        ("ATAN", DataKind::Float, CompileFunc::Float(c_atan)),
+        ("ATAN2", DataKind::Float, CompileFunc::Float(c_atan2)),
+        ("PI", DataKind::Float, CompileFunc::Float(c_pi)),
+        ("RANDOM", DataKind::Float, CompileFunc::Float(c_random)),
+        ("RANDOMINT", DataKind::Int, CompileFunc::Int(c_randomint)),
+        ("SHA256", DataKind::Binary, CompileFunc::Value(c_sha256)),
+        ("SHA1", DataKind::Binary, CompileFunc::Value(c_sha1)),
+        ("MD5", DataKind::Binary, CompileFunc::Value(c_md5)),
+        (
+            "HMACSHA256",
+            DataKind::Binary,
+            CompileFunc::Value(c_hmacsha256),
+        ),
+        ("CTEQUAL", DataKind::Bool, CompileFunc::Bool(c_ctequal)),
+        (
+            "BASE64ENCODE",
+            DataKind::String,
+            CompileFunc::Value(c_base64encode),
+        ),
+        (
+            "BASE64URLENCODE",
+            DataKind::String,
+            CompileFunc::Value(c_base64urlencode),
+        ),
+        (
+            "BASE64DECODE",
+            DataKind::Binary,
+            CompileFunc::Value(c_base64decode),
+        ),
+        (
+            "BASE64URLDECODE",
+            DataKind::Binary,
+            CompileFunc::Value(c_base64decode),
+        ),
+        (
+            "HEXENCODE",
+            DataKind::String,
+            CompileFunc::Value(c_hexencode),
+        ),
+        (
+            "HEXDECODE",
+            DataKind::Binary,
+            CompileFunc::Value(c_hexdecode),
+        ),
+        ("COMPRESS", DataKind::Binary, CompileFunc::Value(c_compress)),
+        (
+            "DECOMPRESS",
+            DataKind::Binary,
+            CompileFunc::Value(c_decompress),
+        ),
+        (
+            "JSON_VALUE",
+            DataKind::String,
+            CompileFunc::Value(c_json_value),
+        ),
+        (
+            "JSON_QUERY",
+            DataKind::String,
+            CompileFunc::Value(c_json_query),
+        ),
+        (
+            "JSONESCAPE",
+            DataKind::String,
+            CompileFunc::Value(c_jsonescape),
+        ),
     ];
     for (name, typ, cf) in list {
         map.insert(name.to_string(), (typ, cf));
     }
 }
+/// Standard builtin functions whose result depends only on their arguments ( the only builtins allowed in index expressions ).
+pub(crate) const DETERMINISTIC: &[&str] = &[
+    "CONTAINS",
+    "REPLACE",
+    "SUBSTRING",
+    "BINSUBSTRING",
+    "LEN",
+    "BINLEN",
+    "PARSEINT",
+    "PARSEFLOAT",
+    "BINTOSTR",
+    "ABS",
+    "FABS",
+    "ROUND",
+    "FLOOR",
+    "CEILING",
+    "POWER",
+    "SQRT",
+    "LOG",
+    "LOG10",
+    "EXP",
+    "SIN",
+    "COS",
+    "TAN",
+    "ASIN",
+    "ACOS",
+    "ATAN",
+    "ATAN2",
+    "PI",
+    "SHA256",
+    "SHA1",
+    "MD5",
+    "HMACSHA256",
+    "CTEQUAL",
+    "BASE64ENCODE",
+    "BASE64URLENCODE",
+    "BASE64DECODE",
+    "BASE64URLDECODE",
+    "HEXENCODE",
+    "HEXDECODE",
+    "COMPRESS",
+    "DECOMPRESS",
+    "JSON_VALUE",
+    "JSON_QUERY",
+    "JSONESCAPE",
+];
 /// Check number and kinds of arguments.
 pub fn check_types(b: &Block, args: &mut [Expr], dk: &[DataKind]) {
     if args.len() != dk.len() {
@@ -72,6 +212,24 @@ pub fn check_types(b: &Block, args: &mut [Expr], dk: &[DataKind]) {
         }
     }
 }
+/// Check arg is a string or binary, and compile it ( for functions that operate on bytes ).
+fn c_bytes(b: &Block, e: &mut Expr) -> CExpPtr<Value> {
+    let k = b.kind(e);
+    if k != DataKind::String && k != DataKind::Binary {
+        panic!(
+            "Builtin function arg type mismatch expected String or Binary got {:?}",
+            k
+        );
+    }
+    c_value(b, e)
+}
+/// Get bytes of a string or binary value.
+fn bytes(v: &Value) -> &[u8] {
+    match v {
+        Value::String(s) => s.as_bytes(),
+        _ => v.bina(),
+    }
+}
 /////////////////////////////
 /// Compile call to EXCEPTION().
 fn c_exception(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
@@ -86,6 +244,43 @@ impl CExp<Value> for Exception {
     }
 }
 /////////////////////////////
+/// Compile call to ERRORMESSAGE().
+fn c_error_message(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    check_types(b, args, &[]);
+    Box::new(ErrorMessage {})
+}
+struct ErrorMessage {}
+impl CExp<Value> for ErrorMessage {
+    fn eval(&self, e: &mut EvalEnv, _d: &[u8]) -> Value {
+        let msg = e.caught.as_ref().map_or("", |x| &x.msg);
+        Value::String(Rc::new(msg.to_string()))
+    }
+}
+/////////////////////////////
+/// Compile call to ERRORLINE().
+fn c_error_line(b: &Block, args: &mut [Expr]) -> CExpPtr<i64> {
+    check_types(b, args, &[]);
+    Box::new(ErrorLine {})
+}
+struct ErrorLine {}
+impl CExp<i64> for ErrorLine {
+    fn eval(&self, e: &mut EvalEnv, _d: &[u8]) -> i64 {
+        e.caught.as_ref().map_or(0, |x| x.line as i64)
+    }
+}
+/////////////////////////////
+/// Compile call to ERRORNUMBER().
+fn c_error_number(b: &Block, args: &mut [Expr]) -> CExpPtr<i64> {
+    check_types(b, args, &[]);
+    Box::new(ErrorNumber {})
+}
+struct ErrorNumber {}
+impl CExp<i64> for ErrorNumber {
+    fn eval(&self, e: &mut EvalEnv, _d: &[u8]) -> i64 {
+        e.caught.as_ref().map_or(0, |x| x.number)
+    }
+}
+/////////////////////////////
 /// Compile call to LEN.
 fn c_len(b: &Block, args: &mut [Expr]) -> CExpPtr<i64> {
     check_types(b, args, &[DataKind::String]);
@@ -435,8 +630,57 @@ impl CExp<i64> for RepackFile {
     }
 }
 
-#[cfg(feature = "verify")]
-/// SQL to load every table ( required for database::verify to work correctly ).
+#[cfg(feature = "pack")]
+/// Compile call to REPACKDB.
+fn c_repackdb(b: &Block, args: &mut [Expr]) -> CExpPtr<i64> {
+    check_types(b, args, &[DataKind::Int]);
+    let budget = c_int(b, &mut args[0]);
+    Box::new(RepackDb { budget })
+}
+#[cfg(feature = "pack")]
+/// Compiled call to REPACKDB.
+struct RepackDb {
+    budget: CExpPtr<i64>,
+}
+#[cfg(feature = "pack")]
+impl CExp<i64> for RepackDb {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> i64 {
+        let budget = self.budget.eval(ee, d);
+        ee.db.run(LOADALLTABLES, ee.tr);
+        ee.db.repack_db(budget)
+    }
+}
+
+/// Compile call to BUILDINDEX.
+fn c_buildindex(b: &Block, args: &mut [Expr]) -> CExpPtr<i64> {
+    check_types(
+        b,
+        args,
+        &[DataKind::String, DataKind::String, DataKind::Int],
+    );
+    let s = c_value(b, &mut args[0]);
+    let t = c_value(b, &mut args[1]);
+    let n = c_int(b, &mut args[2]);
+    Box::new(BuildIndex { s, t, n })
+}
+/// Compiled call to BUILDINDEX( schema, table, n ) : adds up to n rows to an index being built ONLINE.
+/// Result is the number of rows read, zero when there is nothing left to build.
+struct BuildIndex {
+    s: CExpPtr<Value>,
+    t: CExpPtr<Value>,
+    n: CExpPtr<i64>,
+}
+impl CExp<i64> for BuildIndex {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> i64 {
+        let s = self.s.eval(ee, d).str();
+        let t = self.t.eval(ee, d).str();
+        let n = self.n.eval(ee, d);
+        ee.build_index(&ObjRef::new(&s, &t), n as usize)
+    }
+}
+
+#[cfg(any(feature = "verify", feature = "renumber", feature = "pack"))]
+/// SQL to load every table ( required for database::verify, renumber and repack_db to work correctly ).
 const LOADALLTABLES: &str = "  
   DECLARE sid int, sname string, tname string
   FOR sid = Id, sname = Name FROM sys.Schema
@@ -503,3 +747,414 @@ impl CExp<Value> for Bintostr {
         Value::String(Rc::new(String::from_utf8(bytes.bina().to_vec()).unwrap()))
     }
 }
+
+/////////////////////////////
+/// Compile call to ABS.
+fn c_abs(b: &Block, args: &mut [Expr]) -> CExpPtr<i64> {
+    check_types(b, args, &[DataKind::Int]);
+    let x = c_int(b, &mut args[0]);
+    Box::new(Abs { x })
+}
+struct Abs {
+    x: CExpPtr<i64>,
+}
+impl CExp<i64> for Abs {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> i64 {
+        let x = self.x.eval(ee, d);
+        match x.checked_abs() {
+            Some(x) => x,
+            None => panic!("ABS overflow for {}", x),
+        }
+    }
+}
+
+/// Compiled call to a float function of one float argument.
+struct FloatFn {
+    x: CExpPtr<f64>,
+    f: fn(f64) -> f64,
+}
+impl CExp<f64> for FloatFn {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> f64 {
+        (self.f)(self.x.eval(ee, d))
+    }
+}
+/// Compile call to a float function of one float argument.
+fn c_float_fn(b: &Block, args: &mut [Expr], f: fn(f64) -> f64) -> CExpPtr<f64> {
+    check_types(b, args, &[DataKind::Float]);
+    let x = c_float(b, &mut args[0]);
+    Box::new(FloatFn { x, f })
+}
+/// Compile call to FABS.
+fn c_fabs(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::abs)
+}
+/// Compile call to FLOOR.
+fn c_floor(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::floor)
+}
+/// Compile call to CEILING.
+fn c_ceiling(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::ceil)
+}
+/// Compile call to SQRT.
+fn c_sqrt(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::sqrt)
+}
+/// Compile call to LOG ( natural logarithm ).
+fn c_log(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::ln)
+}
+/// Compile call to LOG10.
+fn c_log10(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::log10)
+}
+/// Compile call to EXP.
+fn c_exp(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::exp)
+}
+/// Compile call to SIN.
+fn c_sin(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::sin)
+}
+/// Compile call to COS.
+fn c_cos(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::cos)
+}
+/// Compile call to TAN.
+fn c_tan(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::tan)
+}
+/// Compile call to ASIN.
+fn c_asin(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::asin)
+}
+/// Compile call to ACOS.
+fn c_acos(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::acos)
+}
+/// Compile call to ATAN.
+fn c_atan(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    c_float_fn(b, args, f64::atan)
+}
+
+/////////////////////////////
+/// Compile call to ROUND.
+fn c_round(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    check_types(b, args, &[DataKind::Float, DataKind::Int]);
+    let x = c_float(b, &mut args[0]);
+    let digits = c_int(b, &mut args[1]);
+    Box::new(Round { x, digits })
+}
+struct Round {
+    x: CExpPtr<f64>,
+    digits: CExpPtr<i64>,
+}
+impl CExp<f64> for Round {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> f64 {
+        let x = self.x.eval(ee, d);
+        let digits = self.digits.eval(ee, d).clamp(-300, 300) as i32;
+        let scale = 10f64.powi(digits);
+        (x * scale).round() / scale
+    }
+}
+
+/////////////////////////////
+/// Compile call to POWER.
+fn c_power(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    check_types(b, args, &[DataKind::Float, DataKind::Float]);
+    let x = c_float(b, &mut args[0]);
+    let y = c_float(b, &mut args[1]);
+    Box::new(Power { x, y })
+}
+struct Power {
+    x: CExpPtr<f64>,
+    y: CExpPtr<f64>,
+}
+impl CExp<f64> for Power {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> f64 {
+        let x = self.x.eval(ee, d);
+        let y = self.y.eval(ee, d);
+        x.powf(y)
+    }
+}
+
+/////////////////////////////
+/// Compile call to ATAN2.
+fn c_atan2(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    check_types(b, args, &[DataKind::Float, DataKind::Float]);
+    let y = c_float(b, &mut args[0]);
+    let x = c_float(b, &mut args[1]);
+    Box::new(Atan2 { y, x })
+}
+struct Atan2 {
+    y: CExpPtr<f64>,
+    x: CExpPtr<f64>,
+}
+impl CExp<f64> for Atan2 {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> f64 {
+        let y = self.y.eval(ee, d);
+        let x = self.x.eval(ee, d);
+        y.atan2(x)
+    }
+}
+
+/////////////////////////////
+/// Compile call to PI.
+fn c_pi(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    check_types(b, args, &[]);
+    Box::new(crate::cexp::Const(std::f64::consts::PI))
+}
+
+/////////////////////////////
+/// Compile call to RANDOM.
+fn c_random(b: &Block, args: &mut [Expr]) -> CExpPtr<f64> {
+    check_types(b, args, &[]);
+    Box::new(Random {})
+}
+struct Random {}
+impl CExp<f64> for Random {
+    fn eval(&self, ee: &mut EvalEnv, _d: &[u8]) -> f64 {
+        // 53 random bits give a uniform float in [0,1).
+        (ee.tr.random() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/////////////////////////////
+/// Compile call to RANDOMINT.
+fn c_randomint(b: &Block, args: &mut [Expr]) -> CExpPtr<i64> {
+    check_types(b, args, &[DataKind::Int, DataKind::Int]);
+    let lo = c_int(b, &mut args[0]);
+    let hi = c_int(b, &mut args[1]);
+    Box::new(RandomInt { lo, hi })
+}
+struct RandomInt {
+    lo: CExpPtr<i64>,
+    hi: CExpPtr<i64>,
+}
+impl CExp<i64> for RandomInt {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> i64 {
+        let lo = self.lo.eval(ee, d);
+        let hi = self.hi.eval(ee, d);
+        if hi < lo {
+            panic!("RANDOMINT upper bound less than lower bound");
+        }
+        let range = hi.wrapping_sub(lo) as u64;
+        let r = ee.tr.random();
+        let r = if range == u64::MAX {
+            r
+        } else {
+            r % (range + 1)
+        };
+        lo.wrapping_add(r as i64)
+    }
+}
+
+/////////////////////////////
+/// Compiled call to a function that maps bytes to bytes.
+struct BytesFn {
+    x: CExpPtr<Value>,
+    f: fn(&[u8]) -> Vec<u8>,
+}
+impl CExp<Value> for BytesFn {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> Value {
+        let x = self.x.eval(ee, d);
+        Value::RcBinary(Rc::new((self.f)(bytes(&x))))
+    }
+}
+/// Compile call to a function of one string or binary argument returning binary.
+fn c_bytes_fn(b: &Block, args: &mut [Expr], f: fn(&[u8]) -> Vec<u8>) -> CExpPtr<Value> {
+    if args.len() != 1 {
+        panic!("wrong number of args");
+    }
+    let x = c_bytes(b, &mut args[0]);
+    Box::new(BytesFn { x, f })
+}
+/// Compile call to SHA256.
+fn c_sha256(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    c_bytes_fn(b, args, |x| hash::sha256(x).to_vec())
+}
+/// Compile call to SHA1.
+fn c_sha1(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    c_bytes_fn(b, args, |x| hash::sha1(x).to_vec())
+}
+/// Compile call to MD5.
+fn c_md5(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    c_bytes_fn(b, args, |x| hash::md5(x).to_vec())
+}
+/// Compile call to BASE64DECODE or BASE64URLDECODE ( either alphabet is accepted ).
+fn c_base64decode(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    check_types(b, args, &[DataKind::String]);
+    c_bytes_fn(b, args, util::from_base64)
+}
+/// Compile call to HEXDECODE.
+fn c_hexdecode(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    check_types(b, args, &[DataKind::String]);
+    c_bytes_fn(b, args, |x| {
+        let x = x.strip_prefix(b"0x").unwrap_or(x);
+        if x.len() % 2 != 0 || !x.iter().all(u8::is_ascii_hexdigit) {
+            panic!("invalid hex string");
+        }
+        util::parse_hex(x)
+    })
+}
+/// Compile call to COMPRESS.
+fn c_compress(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    check_types(b, args, &[DataKind::Binary]);
+    c_bytes_fn(b, args, compress::compress)
+}
+/// Compile call to DECOMPRESS.
+fn c_decompress(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    check_types(b, args, &[DataKind::Binary]);
+    c_bytes_fn(b, args, compress::decompress)
+}
+
+/////////////////////////////
+/// Compiled call to a function that maps bytes to a string.
+struct EncodeFn {
+    x: CExpPtr<Value>,
+    f: fn(&[u8]) -> String,
+}
+impl CExp<Value> for EncodeFn {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> Value {
+        let x = self.x.eval(ee, d);
+        Value::String(Rc::new((self.f)(bytes(&x))))
+    }
+}
+/// Compile call to a function of one binary argument returning a string.
+fn c_encode_fn(b: &Block, args: &mut [Expr], f: fn(&[u8]) -> String) -> CExpPtr<Value> {
+    check_types(b, args, &[DataKind::Binary]);
+    let x = c_value(b, &mut args[0]);
+    Box::new(EncodeFn { x, f })
+}
+/// Compile call to BASE64ENCODE.
+fn c_base64encode(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    c_encode_fn(b, args, |x| util::to_base64(x, false))
+}
+/// Compile call to BASE64URLENCODE.
+fn c_base64urlencode(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    c_encode_fn(b, args, |x| util::to_base64(x, true))
+}
+/// Compile call to HEXENCODE.
+fn c_hexencode(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    c_encode_fn(b, args, |x| {
+        let mut s = String::with_capacity(x.len() * 2);
+        util::push_hex(&mut s, x);
+        s
+    })
+}
+
+/////////////////////////////
+/// Compile call to HMACSHA256.
+fn c_hmacsha256(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    if args.len() != 2 {
+        panic!("wrong number of args");
+    }
+    let key = c_bytes(b, &mut args[0]);
+    let msg = c_bytes(b, &mut args[1]);
+    Box::new(HmacSha256 { key, msg })
+}
+struct HmacSha256 {
+    key: CExpPtr<Value>,
+    msg: CExpPtr<Value>,
+}
+impl CExp<Value> for HmacSha256 {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> Value {
+        let key = self.key.eval(ee, d);
+        let msg = self.msg.eval(ee, d);
+        let result = hash::hmac_sha256(bytes(&key), bytes(&msg));
+        Value::RcBinary(Rc::new(result.to_vec()))
+    }
+}
+
+/////////////////////////////
+/// Compile call to CTEQUAL ( constant-time comparison, for checking signatures and tokens ).
+fn c_ctequal(b: &Block, args: &mut [Expr]) -> CExpPtr<bool> {
+    if args.len() != 2 {
+        panic!("wrong number of args");
+    }
+    let x = c_bytes(b, &mut args[0]);
+    let y = c_bytes(b, &mut args[1]);
+    Box::new(CtEqual { x, y })
+}
+struct CtEqual {
+    x: CExpPtr<Value>,
+    y: CExpPtr<Value>,
+}
+impl CExp<bool> for CtEqual {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> bool {
+        let x = self.x.eval(ee, d);
+        let y = self.y.eval(ee, d);
+        hash::ct_equal(bytes(&x), bytes(&y))
+    }
+}
+
+/////////////////////////////
+/// Compile call to JSON_VALUE.
+fn c_json_value(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    check_types(b, args, &[DataKind::String, DataKind::String]);
+    let js = c_value(b, &mut args[0]);
+    let path = c_value(b, &mut args[1]);
+    Box::new(JsonValue { js, path })
+}
+struct JsonValue {
+    js: CExpPtr<Value>,
+    path: CExpPtr<Value>,
+}
+impl CExp<Value> for JsonValue {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> Value {
+        let js = self.js.eval(ee, d).str();
+        let path = self.path.eval(ee, d).str();
+        let j = json::Json::parse(&js);
+        let result = match j.get(&path) {
+            Some(json::Json::String(s)) => s.clone(),
+            Some(json::Json::Number(n)) => n.clone(),
+            Some(json::Json::Bool(b)) => b.to_string(),
+            _ => String::new(),
+        };
+        Value::String(Rc::new(result))
+    }
+}
+
+/////////////////////////////
+/// Compile call to JSON_QUERY.
+fn c_json_query(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    check_types(b, args, &[DataKind::String, DataKind::String]);
+    let js = c_value(b, &mut args[0]);
+    let path = c_value(b, &mut args[1]);
+    Box::new(JsonQuery { js, path })
+}
+struct JsonQuery {
+    js: CExpPtr<Value>,
+    path: CExpPtr<Value>,
+}
+impl CExp<Value> for JsonQuery {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> Value {
+        let js = self.js.eval(ee, d).str();
+        let path = self.path.eval(ee, d).str();
+        let j = json::Json::parse(&js);
+        let mut result = String::new();
+        if let Some(x @ (json::Json::Object(_) | json::Json::Array(_))) = j.get(&path) {
+            x.write(&mut result);
+        }
+        Value::String(Rc::new(result))
+    }
+}
+
+/////////////////////////////
+/// Compile call to JSONESCAPE.
+fn c_jsonescape(b: &Block, args: &mut [Expr]) -> CExpPtr<Value> {
+    check_types(b, args, &[DataKind::String]);
+    let s = c_value(b, &mut args[0]);
+    Box::new(JsonEscape { s })
+}
+struct JsonEscape {
+    s: CExpPtr<Value>,
+}
+impl CExp<Value> for JsonEscape {
+    fn eval(&self, ee: &mut EvalEnv, d: &[u8]) -> Value {
+        let s = self.s.eval(ee, d).str();
+        let mut result = String::with_capacity(s.len());
+        json::push_escaped(&mut result, &s);
+        Value::String(Rc::new(result))
+    }
+}
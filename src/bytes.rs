@@ -1,4 +1,4 @@
-use crate::{util, Cell, Ordering, Rc, Record, SaveOp, SortedFile, DB};
+use crate::{util, Cell, FileState, Ordering, Rc, Record, SaveOp, SortedFile, DB};
 
 /// Number of fragment types.
 pub const NFT: usize = 4;
@@ -72,6 +72,22 @@ impl ByteStorage {
         self.file.changed()
     }
 
+    /// Record unsaved state ( for a savepoint ).
+    pub fn state(&self) -> (FileState, u64) {
+        (self.file.state(), self.id_gen.get())
+    }
+
+    /// Restore unsaved state from a savepoint.
+    pub fn restore(&self, s: &(FileState, u64)) {
+        self.file.restore(&s.0);
+        self.id_gen.set(s.1);
+    }
+
+    /// Release state recorded for a savepoint that is no longer needed.
+    pub fn release(&self, s: &(FileState, u64)) {
+        self.file.release(&s.0);
+    }
+
     /// Save to underlying file.
     pub fn save(&self, db: &DB, op: SaveOp) {
         self.file.save(db, op);
@@ -144,11 +160,11 @@ impl ByteStorage {
         }
     }
 
-    /// Pack underlying file.
+    /// Pack underlying file, stopping once a limit of the budget is reached.
     #[cfg(feature = "pack")]
-    pub fn repack_file(&self, db: &DB) -> i64 {
+    pub fn repack_file(&self, db: &DB, b: &mut crate::sortedfile::RepackBudget) -> i64 {
         let r = Fragment::new(0, self.bpf);
-        self.file.repack(db, &r)
+        self.file.repack(db, &r, b)
     }
 }
 
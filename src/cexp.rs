@@ -1,4 +1,6 @@
-use crate::{get_bytes, util, CExp, CExpPtr, EvalEnv, Function, Rc, Value};
+use crate::{
+    compress, get_bytes, util, CExp, CExpPtr, DataType, EvalEnv, Function, Ordering, Rc, Value,
+};
 
 /// Function call.
 pub(crate) struct Call {
@@ -231,6 +233,29 @@ where
     }
 }
 
+pub(crate) struct CollateCompare {
+    pub c1: CExpPtr<Value>,
+    pub c2: CExpPtr<Value>,
+    pub typ: DataType,
+    pub test: fn(Ordering) -> bool,
+}
+
+impl CExp<bool> for CollateCompare {
+    fn eval(&self, e: &mut EvalEnv, d: &[u8]) -> bool {
+        let v1 = self.c1.eval(e, d);
+        let v2 = self.c2.eval(e, d);
+        (self.test)(v1.collate_cmp(&v2, self.typ))
+    }
+}
+
+pub(crate) struct Collate(pub CExpPtr<Value>, pub DataType);
+
+impl CExp<Value> for Collate {
+    fn eval(&self, e: &mut EvalEnv, d: &[u8]) -> Value {
+        self.0.eval(e, d).collate(self.1)
+    }
+}
+
 pub(crate) struct ColumnI64 {
     pub off: usize,
 }
@@ -308,11 +333,15 @@ impl CExp<Value> for ColumnString {
 pub(crate) struct ColumnBinary {
     pub off: usize,
     pub size: usize,
+    pub compressed: bool,
 }
 
 impl CExp<Value> for ColumnBinary {
     fn eval(&self, ee: &mut EvalEnv, data: &[u8]) -> Value {
-        let bytes = get_bytes(&ee.db, &data[self.off..], self.size).0;
+        let mut bytes = get_bytes(&ee.db, &data[self.off..], self.size).0;
+        if self.compressed {
+            bytes = compress::decompress(&bytes);
+        }
         Value::RcBinary(Rc::new(bytes))
     }
 }
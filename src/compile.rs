@@ -22,6 +22,20 @@ pub fn c_check(b: &Block, e: &mut Expr) {
                 panic!("unknown function {}", name);
             }
         }
+        ExprIs::Binary(Token::In, b1, b2) => {
+            c_check(b, b1);
+            e.is_constant = b1.is_constant;
+            for x in in_list(b2) {
+                c_check(b, x);
+                if !x.is_constant {
+                    e.is_constant = false;
+                }
+                if data_kind(x.data_type) != data_kind(b1.data_type) {
+                    panic!("IN type mismatch")
+                }
+            }
+            e.data_type = BOOL;
+        }
         ExprIs::Binary(op, b1, b2) => {
             c_check(b, b1);
             c_check(b, b2);
@@ -151,7 +165,11 @@ pub fn c_value(b: &Block, e: &mut Expr) -> CExpPtr<Value> {
                 let size = data_size(typ);
                 match data_kind(typ) {
                     DataKind::String => Box::new(cexp::ColumnString { off, size }),
-                    DataKind::Binary => Box::new(cexp::ColumnBinary { off, size }),
+                    DataKind::Binary => Box::new(cexp::ColumnBinary {
+                        off,
+                        size,
+                        compressed: typ & COMPRESSED != 0,
+                    }),
                     _ => panic!(),
                 }
             }
@@ -219,6 +237,7 @@ pub fn c_float(b: &Block, e: &mut Expr) -> CExpPtr<f64> {
                 _ => panic!(),
             }
         }
+        ExprIs::Const(Value::Float(f)) => Box::new(cexp::Const::<f64>(*f)),
         ExprIs::Local(num) => Box::new(cexp::Local(*num)),
         ExprIs::Binary(op, b1, b2) => c_arithmetic(b, *op, b1, b2, c_float),
         ExprIs::Minus(x) => Box::new(cexp::Minus::<f64>(c_float(b, x))),
@@ -241,6 +260,20 @@ pub fn c_bool(b: &Block, e: &mut Expr) -> CExpPtr<bool> {
         }
         ExprIs::Const(Value::Bool(b)) => Box::new(cexp::Const::<bool>(*b)),
         ExprIs::Local(x) => Box::new(cexp::Local(*x)),
+        ExprIs::BuiltinCall(n, a) => c_builtin_bool(b, n, a),
+        ExprIs::Binary(Token::In, b1, b2) => {
+            // x IN ( a, b, c ) is compiled as x = a OR x = b OR x = c.
+            let mut result: Option<CExpPtr<bool>> = None;
+            for x in in_list(b2) {
+                let c = c_comparison(b, Token::Equal, b1, x);
+                result = Some(if let Some(r) = result {
+                    Box::new(cexp::Or(r, c))
+                } else {
+                    c
+                });
+            }
+            result.unwrap()
+        }
         ExprIs::Binary(op, b1, b2) => {
             if *op == Token::Or || *op == Token::And {
                 let c1 = c_bool(b, b1);
@@ -251,12 +284,7 @@ pub fn c_bool(b: &Block, e: &mut Expr) -> CExpPtr<bool> {
                     _ => panic!(),
                 }
             } else {
-                match b.kind(b1) {
-                    DataKind::Bool => c_compare(b, *op, b1, b2, c_bool),
-                    DataKind::Int => c_compare(b, *op, b1, b2, c_int),
-                    DataKind::Float => c_compare(b, *op, b1, b2, c_float),
-                    _ => c_compare(b, *op, b1, b2, c_value),
-                }
+                c_comparison(b, *op, b1, b2)
             }
         }
         ExprIs::Not(x) => Box::new(cexp::Not(c_bool(b, x))),
@@ -266,6 +294,30 @@ pub fn c_bool(b: &Block, e: &mut Expr) -> CExpPtr<bool> {
     }
 }
 
+/// Get the list of values for the right operand of IN.
+pub fn in_list(e: &mut Expr) -> &mut [Expr] {
+    if !matches!(e.exp, ExprIs::List(_)) {
+        std::slice::from_mut(e)
+    } else if let ExprIs::List(x) = &mut e.exp {
+        x
+    } else {
+        unreachable!()
+    }
+}
+
+/// Compile comparison of operands of any kind.
+fn c_comparison(b: &Block, op: Token, e1: &mut Expr, e2: &mut Expr) -> CExpPtr<bool> {
+    match b.kind(e1) {
+        DataKind::Bool => c_compare(b, op, e1, e2, c_bool),
+        DataKind::Int => c_compare(b, op, e1, e2, c_int),
+        DataKind::Float => c_compare(b, op, e1, e2, c_float),
+        DataKind::String if (e1.data_type | e2.data_type) & COLLATION != 0 => {
+            c_collate_compare(b, op, e1, e2)
+        }
+        _ => c_compare(b, op, e1, e2, c_value),
+    }
+}
+
 /// Compile arithmetic.
 fn c_arithmetic<T>(
     b: &Block,
@@ -318,6 +370,27 @@ where
     }
 }
 
+/// Compile string comparison using column collation ( left operand collation takes precedence ).
+fn c_collate_compare(b: &Block, op: Token, e1: &mut Expr, e2: &mut Expr) -> CExpPtr<bool> {
+    let typ = if e1.data_type & COLLATION != 0 {
+        e1.data_type
+    } else {
+        e2.data_type
+    };
+    let test = match op {
+        Token::Equal => Ordering::is_eq,
+        Token::NotEqual => Ordering::is_ne,
+        Token::Less => Ordering::is_lt,
+        Token::Greater => Ordering::is_gt,
+        Token::LessEqual => Ordering::is_le,
+        Token::GreaterEqual => Ordering::is_ge,
+        _ => panic!(),
+    };
+    let c1 = c_value(b, e1);
+    let c2 = c_value(b, e2);
+    Box::new(cexp::CollateCompare { c1, c2, typ, test })
+}
+
 /// Compile CASE Expression.
 fn c_case<T>(
     b: &Block,
@@ -354,6 +427,14 @@ fn c_builtin_float(b: &Block, name: &str, args: &mut [Expr]) -> CExpPtr<f64> {
     panic!()
 }
 
+/// Compile a call to a builtin function that returns a bool.
+fn c_builtin_bool(b: &Block, name: &str, args: &mut [Expr]) -> CExpPtr<bool> {
+    if let Some((_dk, CompileFunc::Bool(cf))) = b.db.builtins.get(name) {
+        return cf(b, args);
+    }
+    panic!()
+}
+
 /// Compile UPDATE statement.
 pub fn c_update(
     b: &mut Block,
@@ -378,6 +459,7 @@ pub fn c_update(
     if index_from.is_some() {
         from = index_from;
     }
+    explain::note_from(b, &from, wher);
     b.dop(DO::Update(se, from.unwrap(), w));
 }
 
@@ -391,6 +473,7 @@ pub fn c_delete(b: &mut Block, tname: &ObjRef, wher: &mut Option<Expr>) {
     if index_from.is_some() {
         from = index_from;
     }
+    explain::note_from(b, &from, wher);
     b.dop(DO::Delete(from.unwrap(), w));
 }
 
@@ -430,8 +513,10 @@ pub fn c_select(b: &mut Block, mut x: FromExpression) -> CFromExpression {
     // Is the save necessary?
     let save = mem::replace(&mut b.from, from);
     let mut exps = Vec::new();
+    let mut kinds = Vec::new();
     for (i, e) in x.exps.iter_mut().enumerate() {
         exps.push(c_value(b, e));
+        kinds.push(data_kind(e.data_type));
         if !x.assigns.is_empty() {
             // Check data kind of assigned local matches data kind of expression.
             let (lnum, _) = x.assigns[i];
@@ -442,26 +527,146 @@ pub fn c_select(b: &mut Block, mut x: FromExpression) -> CFromExpression {
             }
         }
     }
-    let (wher, index_from) = c_where(b, table, &mut x.wher);
-    let mut orderby = Vec::new();
-    let mut desc = Vec::new();
-    for (e, a) in &mut x.orderby {
-        let e = c_value(b, e);
-        orderby.push(e);
-        desc.push(*a);
-    }
+    let (mut wher, index_from) = c_where(b, table, &mut x.wher);
+    let mut orderby = c_orderby(b, &mut x.orderby);
     from = mem::replace(&mut b.from, save);
     if index_from.is_some() {
         from = index_from;
     }
+    // If an index ( or the PRIMARY KEY ) gives the rows in ORDER BY order, no sort is needed.
+    let mut order = None;
+    let mut key_order = false;
+    if !x.orderby.is_empty() {
+        match &from {
+            Some(CTableExpression::Base(t)) if t.key_order(&x.orderby, 0) => key_order = true,
+            Some(CTableExpression::KeyGet(t, keys)) => {
+                key_order = t.key_order(&x.orderby, keys.len())
+            }
+            Some(CTableExpression::Base(t)) => {
+                if let Some((index, rev)) = t.index_order(b, &x.wher, &mut x.orderby, None) {
+                    let t = t.clone();
+                    from = Some(CTableExpression::IxGet(t, Vec::new(), index));
+                    order = Some(rev);
+                }
+            }
+            Some(CTableExpression::IxGet(t, keys, index)) => {
+                let from = Some((*index, keys.len()));
+                order = t
+                    .index_order(b, &x.wher, &mut x.orderby, from)
+                    .map(|(_, rev)| rev);
+            }
+            _ => {}
+        }
+        if order.is_some() || key_order {
+            x.orderby.clear();
+            orderby.clear();
+        }
+    }
+    let desc = x.orderby.iter().map(|(_, a)| *a).collect();
+    // If all the columns referenced are stored in the index, the table lookup can be skipped.
+    if let Some(CTableExpression::IxGet(t, keys, index)) = &from {
+        let mut cols = Vec::new();
+        for e in x.exps.iter().chain(x.wher.iter()) {
+            col_refs(e, &mut cols);
+        }
+        for (e, _) in &x.orderby {
+            col_refs(e, &mut cols);
+        }
+        if let Some(offsets) = t.index_offsets(*index, &cols) {
+            let (t, index, nkeys) = (t.clone(), *index, keys.len());
+            let save = b.from.replace(CTableExpression::Base(t.clone()));
+            b.offsets = Some(offsets);
+            exps = x.exps.iter_mut().map(|e| c_value(b, e)).collect();
+            let (w, index_from) = c_where(b, Some(t.clone()), &mut x.wher);
+            orderby = c_orderby(b, &mut x.orderby);
+            b.offsets = None;
+            b.from = save;
+            if let Some(CTableExpression::IxGet(t, keys, index)) = index_from {
+                from = Some(CTableExpression::IxCover(t, keys, index));
+            } else if nkeys == 0 {
+                // Whole index is read in ORDER BY order.
+                from = Some(CTableExpression::IxCover(t, Vec::new(), index));
+            } else {
+                panic!()
+            }
+            wher = w;
+        }
+    }
+    if order == Some(true) {
+        from = from.map(|x| CTableExpression::IxRev(Box::new(x)));
+    }
+    if b.explain.is_some() {
+        explain::note_from(b, &from, &x.wher);
+        if order.is_some() {
+            b.note("index order".to_string());
+        }
+        if key_order {
+            b.note("primary key order".to_string());
+        }
+        if !x.orderby.is_empty() {
+            let keys: Vec<String> = x
+                .orderby
+                .iter()
+                .map(|(e, desc)| {
+                    let s = explain::exp_text(b, e);
+                    if *desc {
+                        s + " DESC"
+                    } else {
+                        s
+                    }
+                })
+                .collect();
+            b.note(format!("sort by {}", keys.join(", ")));
+        }
+    }
     CFromExpression {
         colnames: x.colnames,
+        kinds,
         assigns: x.assigns,
         exps,
         from,
         wher,
         orderby,
         desc,
+        json: false,
+    }
+}
+
+/// Compile ORDER BY expressions.
+fn c_orderby(b: &Block, orderby: &mut [(Expr, bool)]) -> Vec<CExpPtr<Value>> {
+    let mut result = Vec::new();
+    for (e, _) in orderby {
+        let mut ce = c_value(b, e);
+        if e.data_type & COLLATION != 0 {
+            ce = Box::new(cexp::Collate(ce, e.data_type));
+        }
+        result.push(ce);
+    }
+    result
+}
+
+/// Get the column numbers referenced by a checked expression ( usize::MAX is Id ).
+fn col_refs(e: &Expr, cols: &mut Vec<usize>) {
+    match &e.exp {
+        ExprIs::ColName(_) => cols.push(e.col),
+        ExprIs::Binary(_, e1, e2) => {
+            col_refs(e1, cols);
+            col_refs(e2, cols);
+        }
+        ExprIs::Not(e) | ExprIs::Minus(e) => col_refs(e, cols),
+        ExprIs::Case(list, els) => {
+            for (w, t) in list {
+                col_refs(w, cols);
+                col_refs(t, cols);
+            }
+            col_refs(els, cols);
+        }
+        ExprIs::FuncCall(_, list) | ExprIs::BuiltinCall(_, list) | ExprIs::List(list) => {
+            for e in list {
+                col_refs(e, cols);
+            }
+        }
+        _ => {}
     }
 }
 
@@ -519,34 +724,45 @@ pub fn c_table(b: &Block, name: &ObjRef) -> Rc<Table> {
 /// Compile named function (if it is not already compiled ).
 pub fn c_function(db: &DB, name: &ObjRef) -> Rc<Function> {
     if let Some(r) = db.get_function(name) {
-        let (compiled, src) = { (r.compiled.get(), r.source.clone()) };
-        if !compiled {
-            r.compiled.set(true);
-            let mut p = Parser::new(&src, db);
-            p.function_name = Some(name);
-            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                p.parse_function();
-            }));
-            if let Err(x) = result {
-                r.compiled.set(false);
-                std::panic::panic_any(if let Some(sqe) = x.downcast_ref::<SqlError>() {
-                    sqe.clone()
-                } else if let Some(s) = x.downcast_ref::<&str>() {
-                    p.make_error((*s).to_string())
-                } else if let Some(s) = x.downcast_ref::<String>() {
-                    p.make_error(s.to_string())
-                } else {
-                    p.make_error("unrecognised/unexpected error".to_string())
-                });
-            }
-            *r.ilist.borrow_mut() = p.b.ilist;
-        }
+        c_compile(db, &r, None);
         r
     } else {
         panic!("function {} not found", name.str())
     }
 }
 
+/// Compile a function if it has not already been compiled.
+/// For a trigger, trigger has the parameter names and types ( see [crate::trigger::TriggerList] ).
+pub fn c_compile(db: &DB, r: &Function, trigger: Option<(&[String], &[DataType])>) {
+    let (compiled, src) = { (r.compiled.get(), r.source.clone()) };
+    if !compiled {
+        r.compiled.set(true);
+        let mut p = Parser::new(&src, db);
+        p.function_name = Some(&r.name);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            if let Some((names, typ)) = trigger {
+                p.parse_trigger(names, typ);
+            } else {
+                p.parse_function();
+            }
+        }));
+        if let Err(x) = result {
+            r.compiled.set(false);
+            std::panic::panic_any(if let Some(sqe) = x.downcast_ref::<SqlError>() {
+                sqe.clone()
+            } else if let Some(s) = x.downcast_ref::<&str>() {
+                p.make_error((*s).to_string())
+            } else if let Some(s) = x.downcast_ref::<String>() {
+                p.make_error(s.to_string())
+            } else {
+                p.make_error("unrecognised/unexpected error".to_string())
+            });
+        }
+        *r.ilist.borrow_mut() = p.b.ilist;
+        *r.lines.borrow_mut() = p.b.lines;
+    }
+}
+
 /// Lookup the column offset and DataType of a named column.
 pub fn name_to_col(b: &Block, name: &str) -> (usize, DataType) {
     if let Some(CTableExpression::Base(t)) = &b.from {
@@ -556,6 +772,9 @@ pub fn name_to_col(b: &Block, name: &str) -> (usize, DataType) {
             if colnum == usize::MAX {
                 return (0, INT);
             }
+            if let Some(offsets) = &b.offsets {
+                return (offsets[colnum], info.typ[colnum]);
+            }
             return (info.off[colnum], info.typ[colnum]);
         }
     }
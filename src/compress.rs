@@ -0,0 +1,147 @@
+// Simple LZ77 compression.
+//
+// Compressed format is the uncompressed length ( LEB128 ) followed by sequences.
+// Each sequence is a token byte ( high nibble literal count, low nibble match length - 4 ),
+// extra length bytes if a nibble is 15, the literal bytes and then a 2 byte match offset.
+// The final sequence has literals only.
+
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = 0xffff;
+const HASH_BITS: usize = 12;
+
+/// Compress data.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let n = data.len();
+    let mut out = Vec::with_capacity(n / 2 + 16);
+    let mut x = n;
+    while x >= 128 {
+        out.push(x as u8 | 128);
+        x >>= 7;
+    }
+    out.push(x as u8);
+
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let (mut i, mut anchor) = (0, 0);
+    while i + MIN_MATCH <= n {
+        let h = hash(&data[i..]);
+        let cand = table[h];
+        table[h] = i;
+        if cand != usize::MAX
+            && i - cand <= MAX_OFFSET
+            && data[cand..cand + MIN_MATCH] == data[i..i + MIN_MATCH]
+        {
+            let mut len = MIN_MATCH;
+            while i + len < n && data[cand + len] == data[i + len] {
+                len += 1;
+            }
+            put_sequence(&mut out, &data[anchor..i], Some((i - cand, len)));
+            i += len;
+            anchor = i;
+        } else {
+            i += 1;
+        }
+    }
+    put_sequence(&mut out, &data[anchor..], None);
+    out
+}
+
+/// Decompress data produced by [compress].
+pub fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut ix = 0;
+    let (mut n, mut shift) = (0usize, 0);
+    loop {
+        let b = byte(data, &mut ix);
+        if shift > 56 {
+            invalid();
+        }
+        n |= ((b & 127) as usize) << shift;
+        shift += 7;
+        if b < 128 {
+            break;
+        }
+    }
+    if n > data.len().saturating_mul(256) + 64 {
+        invalid();
+    }
+    let mut out = Vec::with_capacity(n);
+    loop {
+        let token = byte(data, &mut ix);
+        let lits = length(data, &mut ix, (token >> 4) as usize);
+        if lits > data.len() - ix || out.len() + lits > n {
+            invalid();
+        }
+        out.extend_from_slice(&data[ix..ix + lits]);
+        ix += lits;
+        if out.len() == n && ix == data.len() {
+            break;
+        }
+        let offset = byte(data, &mut ix) as usize | (byte(data, &mut ix) as usize) << 8;
+        let len = length(data, &mut ix, (token & 15) as usize) + MIN_MATCH;
+        if offset == 0 || offset > out.len() || out.len() + len > n {
+            invalid();
+        }
+        let start = out.len() - offset;
+        for k in start..start + len {
+            out.push(out[k]);
+        }
+    }
+    out
+}
+
+/// Hash of the 4 bytes at the start of data.
+fn hash(data: &[u8]) -> usize {
+    let x = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    (x.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Append a sequence ( literals then optional match ) to out.
+fn put_sequence(out: &mut Vec<u8>, lits: &[u8], m: Option<(usize, usize)>) {
+    let ml = m.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push((lits.len().min(15) << 4 | ml.min(15)) as u8);
+    put_length(out, lits.len());
+    out.extend_from_slice(lits);
+    if let Some((offset, _)) = m {
+        out.push(offset as u8);
+        out.push((offset >> 8) as u8);
+        put_length(out, ml);
+    }
+}
+
+/// Append extra length bytes for a length that does not fit in a nibble.
+fn put_length(out: &mut Vec<u8>, len: usize) {
+    if len >= 15 {
+        let mut x = len - 15;
+        while x >= 255 {
+            out.push(255);
+            x -= 255;
+        }
+        out.push(x as u8);
+    }
+}
+
+/// Read a length given the nibble value from the token.
+fn length(data: &[u8], ix: &mut usize, nibble: usize) -> usize {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let b = byte(data, ix);
+            len += b as usize;
+            if b < 255 {
+                break;
+            }
+        }
+    }
+    len
+}
+
+fn byte(data: &[u8], ix: &mut usize) -> u8 {
+    if *ix >= data.len() {
+        invalid();
+    }
+    *ix += 1;
+    data[*ix - 1]
+}
+
+fn invalid() -> ! {
+    panic!("invalid compressed data")
+}
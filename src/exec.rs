@@ -14,17 +14,115 @@ pub struct EvalEnv<'r> {
     pub tr: &'r mut dyn Transaction,
     /// Function call depth, prevents stack overflow.
     pub call_depth: usize,
+    /// Number of enclosing TRY blocks.
+    pub(crate) try_depth: usize,
+    /// Error number of last THROW.
+    pub(crate) throw_number: i64,
+    /// Error caught by enclosing CATCH block.
+    pub(crate) caught: Option<Rc<CaughtError>>,
+    /// Is profiling enabled.
+    pub(crate) profile: bool,
+    /// Number of rows read from tables ( only counted when profiling ).
+    pub(crate) rows_scanned: u64,
+    /// Number of rows read from tables that satisfied the WHERE condition ( only counted when profiling ).
+    pub(crate) rows_returned: u64,
+    /// Budget of the batch ( if there are limits ).
+    pub(crate) budget: Option<Rc<Budget>>,
+}
+
+/// Counts instructions and rows executed by a batch, raises an error when a [RunLimits] limit is reached.
+pub(crate) struct Budget {
+    limits: RunLimits,
+    start: std::time::Instant,
+    instructions: Cell<u64>,
+    rows: Cell<u64>,
+    exceeded: RefCell<Option<String>>,
+}
+
+impl Budget {
+    /// Construct a Budget, elapsed time is measured from now.
+    pub fn new(limits: RunLimits) -> Self {
+        Self {
+            limits,
+            start: std::time::Instant::now(),
+            instructions: Cell::new(0),
+            rows: Cell::new(0),
+            exceeded: RefCell::new(None),
+        }
+    }
+
+    /// Count an instruction.
+    fn instruction(&self) {
+        let n = self.instructions.get() + 1;
+        self.instructions.set(n);
+        if let Some(max) = self.limits.instructions {
+            if n > max {
+                self.fail(format!("instruction limit of {} exceeded", max));
+            }
+        }
+        if n & 255 == 0 {
+            self.poll();
+        }
+    }
+
+    /// Count a row read from a table.
+    fn row(&self) {
+        let n = self.rows.get() + 1;
+        self.rows.set(n);
+        if let Some(max) = self.limits.rows {
+            if n > max {
+                self.fail(format!("row limit of {} exceeded", max));
+            }
+        }
+        if n & 255 == 0 {
+            self.poll();
+        }
+    }
+
+    /// Check elapsed time and cancellation token.
+    fn poll(&self) {
+        if let Some(max) = self.limits.time {
+            if self.start.elapsed() > max {
+                self.fail(format!("time limit of {:?} exceeded", max));
+            }
+        }
+        if let Some(c) = &self.limits.cancel {
+            if c.load(std::sync::atomic::Ordering::Relaxed) {
+                self.fail("batch cancelled".to_string());
+            }
+        }
+    }
+
+    /// Record that a limit has been reached and raise an error.
+    fn fail(&self, msg: String) -> ! {
+        *self.exceeded.borrow_mut() = Some(msg.clone());
+        panic!("{}", msg)
+    }
+
+    /// Error message if a limit has been reached.
+    pub fn exceeded(&self) -> Option<String> {
+        self.exceeded.borrow().clone()
+    }
 }
 
 impl<'r> EvalEnv<'r> {
     /// Construct a new EvalEnv.
     pub fn new(db: DB, tr: &'r mut dyn Transaction) -> Self {
+        let profile = db.profiler.borrow().is_some();
+        let budget = db.budget.borrow().clone();
         EvalEnv {
+            profile,
+            budget,
+            rows_scanned: 0,
+            rows_returned: 0,
             stack: Vec::with_capacity(64),
             bp: 0,
             db,
             tr,
             call_depth: 0,
+            try_depth: 0,
+            throw_number: 0,
+            caught: None,
         }
     }
 
@@ -36,10 +134,57 @@ impl<'r> EvalEnv<'r> {
         }
     }
 
+    /// Assign args to parameters ( names and types ), locals are the local variable numbers of the parameters.
+    pub(crate) fn bind(&mut self, params: &[(String, DataType)], locals: &[usize], args: &[Value]) {
+        if args.len() != params.len() {
+            panic!("expected {} args got {}", params.len(), args.len());
+        }
+        for (i, arg) in args.iter().enumerate() {
+            let (name, typ) = &params[i];
+            if arg.kind() != data_kind(*typ) {
+                panic!(
+                    "arg type mismatch for {} expected {:?} got {:?}",
+                    name,
+                    data_kind(*typ),
+                    arg.kind()
+                );
+            }
+            self.stack[self.bp + locals[i]] = arg.clone();
+        }
+    }
+
     /// Execute list of instructions.
     pub fn go(&mut self, ilist: &[Instruction]) {
-        let mut ip = 0;
-        while ip < ilist.len() {
+        self.exec(ilist, 0, 0, ilist.len());
+    }
+
+    /// Execute instructions one statement at a time starting at ip, ip is updated to the start of each statement executed.
+    pub(crate) fn go_statements(
+        &mut self,
+        ilist: &[Instruction],
+        lines: &[(usize, usize)],
+        ip: &mut usize,
+    ) {
+        while *ip < ilist.len() {
+            let pos = lines.partition_point(|(i, _)| *i <= *ip);
+            let start = if pos > 0 { lines[pos - 1].0 } else { 0 };
+            let end = lines.get(pos).map_or(ilist.len(), |x| x.0);
+            *ip = self.exec(ilist, *ip, start, end);
+        }
+    }
+
+    /// Execute instructions while ip is in range start..end. Result is next ip ( usize::MAX after Return ).
+    pub(crate) fn exec(
+        &mut self,
+        ilist: &[Instruction],
+        mut ip: usize,
+        start: usize,
+        end: usize,
+    ) -> usize {
+        while ip >= start && ip < end {
+            if let Some(b) = &self.budget {
+                b.instruction();
+            }
             let inst = &ilist[ip];
             ip += 1;
             match inst {
@@ -57,11 +202,13 @@ impl<'r> EvalEnv<'r> {
                     }
                 }
                 Call(x) => self.call(x),
-                Return => break,
+                Return => return usize::MAX,
                 Throw => {
-                    let s = self.pop_string();
-                    panic!("{}", s);
+                    let msg = self.pop_string();
+                    self.throw_number = self.pop_int();
+                    panic!("{}", msg);
                 }
+                Try(info) => ip = self.try_catch(ilist, ip, info),
                 Execute => self.execute(),
                 DataOp(x) => self.exec_do(x),
                 Select(cse) => self.select(cse),
@@ -110,7 +257,72 @@ impl<'r> EvalEnv<'r> {
                 }
             }
         }
-    } // end fn go
+        ip
+    } // end fn exec
+
+    /// Execute TRY block starting at ip, if there is an error execute the CATCH block.
+    fn try_catch(&mut self, ilist: &[Instruction], start: usize, info: &TryInfo) -> usize {
+        let (stack_len, bp, call_depth) = (self.stack.len(), self.bp, self.call_depth);
+        let caught = self.caught.clone();
+        self.throw_number = 0;
+        self.try_depth += 1;
+        let mut ip = start;
+        while ip >= start && ip < info.catch {
+            // Statements are run one at a time, changes made by a statement that fails part way are undone before CATCH runs.
+            let pos = info.lines.partition_point(|(i, _)| *i <= ip);
+            let stmt_start = if pos > 0 {
+                info.lines[pos - 1].0
+            } else {
+                start
+            };
+            let stmt_end = info.lines.get(pos).map_or(info.catch, |x| x.0);
+            let mark = savepoint::mark(&self.db);
+            let result = std::panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                self.exec(ilist, ip, stmt_start, stmt_end)
+            }));
+            match result {
+                Ok(next) => {
+                    savepoint::release(&self.db, mark);
+                    ip = next
+                }
+                Err(x) => {
+                    savepoint::undo(&self.db, mark);
+                    self.try_depth -= 1;
+                    if self.budget.as_ref().is_some_and(|b| b.exceeded().is_some()) {
+                        // Limit errors cannot be caught.
+                        std::panic::resume_unwind(x);
+                    }
+                    self.stack.truncate(stack_len);
+                    self.bp = bp;
+                    self.call_depth = call_depth;
+                    let msg = if let Some(e) = x.downcast_ref::<SqlError>() {
+                        e.msg.clone()
+                    } else if let Some(s) = x.downcast_ref::<&str>() {
+                        s.to_string()
+                    } else if let Some(s) = x.downcast_ref::<String>() {
+                        s.clone()
+                    } else {
+                        "unrecognised/unexpected error".to_string()
+                    };
+                    let line = info.lines.iter().rev().find(|(i, _)| *i <= ip);
+                    self.caught = Some(Rc::new(CaughtError {
+                        msg,
+                        line: line.map_or(0, |x| x.1),
+                        number: std::mem::take(&mut self.throw_number),
+                    }));
+                    let next = self.exec(ilist, info.catch, info.catch, info.end);
+                    self.caught = caught;
+                    return next;
+                }
+            }
+        }
+        self.try_depth -= 1;
+        if ip == info.catch {
+            info.end
+        } else {
+            ip
+        }
+    }
 
     /// Call a function.
     pub fn call(&mut self, r: &Function) {
@@ -128,7 +340,21 @@ impl<'r> EvalEnv<'r> {
         let save_bp = self.bp;
         self.bp = self.stack.len() - r.param_count;
         self.alloc_locals(&r.local_typ, r.param_count);
-        self.go(&r.ilist.borrow());
+        if self.profile {
+            let start = profile::Start::new(self);
+            let name = r.name.str();
+            self.go_profiled(
+                &r.ilist.borrow(),
+                &r.lines.borrow(),
+                &name,
+                &r.source,
+                &mut 0,
+            );
+            let stats = start.stats(self);
+            profile::record_function(&self.db, &name, &stats);
+        } else {
+            self.go(&r.ilist.borrow());
+        }
         let pop_count = r.local_typ.len();
         if pop_count > 0 {
             if r.return_type != NONE {
@@ -166,6 +392,11 @@ impl<'r> EvalEnv<'r> {
         }
     }
 
+    /// Pop int from the stack.
+    fn pop_int(&mut self) -> i64 {
+        self.stack.pop().unwrap().int()
+    }
+
     /// Execute a ForInit instruction. Constructs For state and assigns it to local variable.
     fn for_init(&mut self, for_id: usize, cte: &CTableExpression) {
         let data_source = self.data_source(cte);
@@ -175,11 +406,22 @@ impl<'r> EvalEnv<'r> {
 
     /// Evaluate optional where expression.
     fn ok(&mut self, wher: &Option<CExpPtr<bool>>, data: &[u8]) -> bool {
-        if let Some(w) = wher {
+        if let Some(b) = &self.budget {
+            b.row();
+        }
+        let ok = if let Some(w) = wher {
             w.eval(self, data)
         } else {
             true
+        };
+        // Row counts are only needed by the profiler.
+        if self.profile {
+            self.rows_scanned += 1;
+            if ok {
+                self.rows_returned += 1;
+            }
         }
+        ok
     }
 
     /// Execute a ForNext instruction. Fetches a record from underlying file that satisfies the where condition,
@@ -241,7 +483,17 @@ impl<'r> EvalEnv<'r> {
         let s = self.pop_string();
         #[cfg(feature = "log-execute")]
         println!("EXECUTE {}", s);
-        self.db.run(&s, self.tr);
+        if self.try_depth > 0 {
+            // Error is raised so it can be caught.
+            if let Some(e) = self.db.go(&s, None, self.tr) {
+                panic!("{}", e.msg);
+            }
+        } else {
+            self.db.run(&s, self.tr);
+        }
+        if let Some(msg) = self.budget.as_ref().and_then(|b| b.exceeded()) {
+            panic!("{}", msg);
+        }
     }
 
     /// Execute a data operation (DO).
@@ -260,23 +512,38 @@ impl<'r> EvalEnv<'r> {
             DO::DropSchema(name) => self.drop_schema(name),
             DO::DropTable(name) => self.drop_table(name),
             DO::DropFunction(name) => self.drop_function(name),
+            DO::CreateTrigger(name, tname, events, source) => {
+                trigger::create(&self.db, name, tname, *events, source.clone())
+            }
+            DO::DropTrigger(tname, name) => self.drop_trigger(tname, name),
             DO::DropIndex(tname, iname) => self.drop_index(tname, iname),
             DO::AlterTable(tname, actions) => self.alter_table(tname, actions),
+            DO::Analyze(tname) => self.analyze(tname),
+            DO::Begin => savepoint::begin(&self.db),
+            DO::Commit => savepoint::commit(&self.db),
+            DO::Rollback => savepoint::rollback(&self.db),
+            DO::Savepoint(name) => savepoint::create(&self.db, name),
+            DO::RollbackTo(name) => savepoint::rollback_to(&self.db, name),
         }
     }
 
-    /// Get list of record ids for DELETE/UPDATE.
-    fn get_id_list(&mut self, te: &CTableExpression, w: &Option<CExpPtr<bool>>) -> Vec<u64> {
-        let mut idlist = Vec::new();
+    /// Get list of record keys for DELETE/UPDATE ( see [Table::rec_key] ).
+    fn get_key_list(
+        &mut self,
+        te: &CTableExpression,
+        w: &Option<CExpPtr<bool>>,
+    ) -> Vec<Box<dyn Record>> {
+        let t = te.table();
+        let mut keylist = Vec::new();
 
         for (pp, off) in self.data_source(te) {
             let p = pp.borrow();
             let data = &p.data[off..];
             if self.ok(w, data) {
-                idlist.push(util::getu64(data, 0));
+                keylist.push(t.rec_key(&self.db, data));
             }
         }
-        idlist
+        keylist
     }
 
     /// Execute INSERT operation.
@@ -290,19 +557,21 @@ impl<'r> EvalEnv<'r> {
 
     /// Execute a DELETE operation.
     fn delete(&mut self, from: &CTableExpression, w: &Option<CExpPtr<bool>>) {
-        let idlist = self.get_id_list(from, w);
+        let keylist = self.get_key_list(from, w);
         let t = from.table();
         let mut oldrow = t.row();
-        for id in idlist {
+        for key in keylist {
             // Load oldrow so that any codes are deleted.
-            if let Some((pp, off)) = t.id_get(&self.db, id) {
-                let p = pp.borrow();
-                let data = &p.data[off..];
-                oldrow.load(&self.db, data);
-            } else {
-                unreachable!()
+            // The row may already have been deleted by a trigger.
+            if let Some((pp, off)) = t.file.get(&self.db, &*key) {
+                {
+                    let p = pp.borrow();
+                    let data = &p.data[off..];
+                    oldrow.load(&self.db, data);
+                }
+                t.remove(&self.db, &oldrow);
+                self.fire(&t, trigger::DELETE, Some(&oldrow), None);
             }
-            t.remove(&self.db, &oldrow);
         }
     }
 
@@ -313,11 +582,11 @@ impl<'r> EvalEnv<'r> {
         from: &CTableExpression,
         w: &Option<CExpPtr<bool>>,
     ) {
-        let idlist = self.get_id_list(from, w);
+        let keylist = self.get_key_list(from, w);
         let t = from.table();
         let mut oldrow = t.row();
-        for id in idlist {
-            if let Some((pp, off)) = t.id_get(&self.db, id) {
+        for key in keylist {
+            if let Some((pp, off)) = t.file.get(&self.db, &*key) {
                 let mut newrow = {
                     let p = pp.borrow();
                     let data = &p.data[off..];
@@ -331,6 +600,7 @@ impl<'r> EvalEnv<'r> {
                 // Would be nice to optimise this to minimise re-indexing.
                 t.remove(&self.db, &oldrow);
                 t.insert(&self.db, &mut newrow);
+                self.fire(&t, trigger::UPDATE, Some(&oldrow), Some(&newrow));
             }
         }
     }
@@ -350,12 +620,73 @@ impl<'r> EvalEnv<'r> {
                 }
                 Box::new(t.scan_keys(&self.db, keys, *index))
             }
+            CTableExpression::IxCover(t, val, index) => {
+                let mut keys = Vec::new();
+                for v in val {
+                    keys.push(v.eval(self, &[]));
+                }
+                Box::new(t.scan_cover(&self.db, keys, *index))
+            }
+            CTableExpression::IxRev(x) => {
+                let (t, val, index, cover) = match &**x {
+                    CTableExpression::IxGet(t, val, index) => (t, val, index, false),
+                    CTableExpression::IxCover(t, val, index) => (t, val, index, true),
+                    _ => panic!(),
+                };
+                let mut keys = Vec::new();
+                for v in val {
+                    keys.push(v.eval(self, &[]));
+                }
+                Box::new(t.scan_index(&self.db, keys, *index, cover, true))
+            }
+            CTableExpression::KeyGet(t, val) => {
+                let mut keys = Vec::new();
+                for v in val {
+                    keys.push(v.eval(self, &[]));
+                }
+                Box::new(t.scan_pk(&self.db, keys))
+            }
+            CTableExpression::IdList(t, list) => {
+                let mut ids = Vec::new();
+                for x in list {
+                    ids.push(x.eval(self, &[]));
+                }
+                ids.sort_unstable();
+                ids.dedup();
+                let (t, db) = (t.clone(), self.db.clone());
+                Box::new(ids.into_iter().flat_map(move |id| t.scan_id(&db, id)))
+            }
+            CTableExpression::IxList(t, list, index) => {
+                let mut keys = Vec::new();
+                for x in list {
+                    keys.push(x.eval(self, &[]));
+                }
+                let (t, db, index) = (t.clone(), self.db.clone(), *index);
+                // Keys may match the same rows ( duplicates, collation ), so rows already seen are skipped.
+                let mut seen = HashSet::default();
+                Box::new(
+                    keys.into_iter()
+                        .flat_map(move |k| t.scan_key(&db, k, index))
+                        .filter(move |(pp, off)| {
+                            seen.insert(util::getu64(&pp.borrow().data, *off))
+                        }),
+                )
+            }
             _ => panic!(),
         }
     }
 
     /// Execute a SELECT operation.
     fn select(&mut self, cse: &CFromExpression) {
+        let mut json = if cse.json {
+            self.tr
+                .begin_select(&["JSON".to_string()], &[DataKind::String]);
+            Some(String::from("["))
+        } else {
+            self.tr.begin_select(&cse.colnames, &cse.kinds);
+            None
+        };
+        let mut rows = 0;
         if let Some(te) = &cse.from {
             let obl = cse.orderby.len();
             let mut temp = Vec::new(); // For sorting.
@@ -380,7 +711,8 @@ impl<'r> EvalEnv<'r> {
                         temp.push(values);
                     } else {
                         // Output directly.
-                        self.tr.selected(&values);
+                        self.output(&mut json, cse, &values);
+                        rows += 1;
                     }
                 }
             }
@@ -388,8 +720,9 @@ impl<'r> EvalEnv<'r> {
                 // Sort then output the rows.
                 temp.sort_by(|a, b| table::row_compare(a, b, &cse.desc));
                 for r in &temp {
-                    self.tr.selected(&r[obl..]);
+                    self.output(&mut json, cse, &r[obl..]);
                 }
+                rows += temp.len();
             }
         } else {
             let mut values = Vec::new();
@@ -397,7 +730,35 @@ impl<'r> EvalEnv<'r> {
                 let val = ce.eval(self, &[]);
                 values.push(val);
             }
-            self.tr.selected(&values);
+            self.output(&mut json, cse, &values);
+            rows += 1;
+        }
+        if let Some(mut s) = json {
+            s.push(']');
+            self.tr.selected(&[Value::String(Rc::new(s))]);
+            rows = 1;
+        }
+        self.tr.end_select(rows);
+    }
+
+    /// Output selected row, or append it to JSON output as an object keyed by column names.
+    fn output(&mut self, json: &mut Option<String>, cse: &CFromExpression, values: &[Value]) {
+        if let Some(s) = json {
+            if s.len() > 1 {
+                s.push(',');
+            }
+            s.push('{');
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 {
+                    s.push(',');
+                }
+                json::push_string(s, &cse.colnames[i]);
+                s.push(':');
+                json::push_value(s, v);
+            }
+            s.push('}');
+        } else {
+            self.tr.selected(values);
         }
     }
 
@@ -457,6 +818,7 @@ impl<'r> EvalEnv<'r> {
             }
             self.db.lastid.set(row.id);
             table.insert(&self.db, &mut row);
+            self.fire(&table, trigger::INSERT, None, Some(&row));
         }
     }
 
@@ -490,10 +852,23 @@ impl<'r> EvalEnv<'r> {
 
     fn drop_schema(&mut self, name: &str) {
         if let Some(sid) = sys::get_schema(&self.db, name) {
+            let tables: Vec<(i64, Vec<i64>)> = sys::get_schema_tables(&self.db, sid)
+                .into_iter()
+                .map(|id| (id, sys::get_table_indexes(&self.db, id)))
+                .collect();
             let sql = format!("EXEC sys.DropSchema({})", sid);
             self.db.run(&sql, self.tr);
+            // Index information and triggers of tables that sys.DropSchema deleted.
+            for (id, indexes) in tables {
+                if self.db.sys_table.id_get(&self.db, id as u64).is_none() {
+                    for ix in indexes {
+                        self.drop_index_info(ix);
+                    }
+                    self.drop_triggers(id);
+                }
+            }
             self.db.schemas.borrow_mut().remove(name);
-            self.db.function_reset.set(true);
+            self.db.schema_changed();
         } else {
             panic!("Drop Schema not found {}", name);
         }
@@ -503,8 +878,12 @@ impl<'r> EvalEnv<'r> {
         if let Some(t) = sys::get_table(&self.db, name) {
             let sql = format!("EXEC sys.DropTable({})", t.id);
             self.db.run(&sql, self.tr);
+            for ix in t.ixlist.borrow().iter() {
+                self.drop_index_info(ix.id);
+            }
+            self.drop_triggers(t.id);
             self.db.tables.borrow_mut().remove(name);
-            self.db.function_reset.set(true);
+            self.db.schema_changed();
             t.free_pages(&self.db);
         } else {
             panic!("Drop Table not found {}", name.str());
@@ -515,7 +894,7 @@ impl<'r> EvalEnv<'r> {
         if let Some(fid) = sys::get_function_id(&self.db, name) {
             let sql = format!("DELETE FROM sys.Function WHERE Id = {}", fid);
             self.db.run(&sql, self.tr);
-            self.db.function_reset.set(true);
+            self.db.schema_changed();
         } else {
             panic!("Drop Function not found {}", name.str());
         }
@@ -525,11 +904,71 @@ impl<'r> EvalEnv<'r> {
         let (t, ix, id) = sys::get_index(&self.db, tname, iname);
         let sql = format!("EXEC sys.DropIndex({})", id);
         self.db.run(&sql, self.tr);
+        self.drop_index_info(id as i64);
         self.db.tables.borrow_mut().remove(tname);
-        self.db.function_reset.set(true);
+        self.db.schema_changed();
         t.delete_index(&self.db, ix);
     }
 
+    /// Collect statistics for the indexes of a table ( see [IndexStats] ).
+    fn analyze(&mut self, name: &ObjRef) {
+        if let Some(t) = self.db.get_table(name) {
+            for (index, stats) in t.analyze(&self.db).into_iter().enumerate() {
+                let id = t.ixlist.borrow()[index].id;
+                if sys::index_table(&self.db, "IndexStats", false).is_some() {
+                    let sql = format!("DELETE FROM sys.IndexStats WHERE Index = {}", id);
+                    self.db.run(&sql, self.tr);
+                }
+                sys::save_index_stats(&self.db, id, &stats);
+                t.set_stats(index, stats);
+            }
+            self.db.schema_changed();
+        } else {
+            panic!("table not found: {}", name.str());
+        }
+    }
+
+    /// Add up to n rows to indexes of a table being built ONLINE ( see [Table::build_index] ).
+    /// Result is the number of rows read ( zero if there are no index builds ), or -1 if the table is not found.
+    pub(crate) fn build_index(&mut self, name: &ObjRef, n: usize) -> i64 {
+        let db = self.db.clone();
+        if let Some(t) = db.get_table(name) {
+            while let Some((index, rows)) = t.build_index(&db, n) {
+                let (id, build) = {
+                    let ix = &t.ixlist.borrow()[index];
+                    (ix.id, ix.build)
+                };
+                let sql = if let Some(progress) = build {
+                    format!(
+                        "UPDATE sys.IndexBuild SET Def = '{}' WHERE Index = {}",
+                        progress, id
+                    )
+                } else {
+                    // The build is complete, the index can now be used.
+                    db.schema_changed();
+                    format!("DELETE FROM sys.IndexBuild WHERE Index = {}", id)
+                };
+                db.run(&sql, self.tr);
+                if rows > 0 || build.is_some() {
+                    return rows as i64;
+                }
+            }
+            0
+        } else {
+            -1
+        }
+    }
+
+    /// Delete extra information ( WHERE condition, key expressions, statistics, build progress ) for a dropped index.
+    fn drop_index_info(&mut self, id: i64) {
+        for name in sys::INDEX_TABLES {
+            if sys::index_table(&self.db, name, false).is_some() {
+                let sql = format!("DELETE FROM sys.{} WHERE Index = {}", name, id);
+                self.db.run(&sql, self.tr);
+            }
+        }
+    }
+
     fn alter_table(&mut self, name: &ObjRef, actions: &[AlterCol]) {
         let db = &self.db;
         if let Some(t) = sys::get_table(db, name) {
@@ -543,6 +982,11 @@ impl<'r> EvalEnv<'r> {
                         if !t.info.colmap.contains_key(name) {
                             panic!("column not found {}", name);
                         }
+                        if matches!(act, AlterCol::Drop(_))
+                            && t.info.key.contains(&t.info.colmap[name])
+                        {
+                            panic!("cannot drop PRIMARY KEY column {}", name);
+                        }
                     }
                     _ => {}
                 }
@@ -551,10 +995,16 @@ impl<'r> EvalEnv<'r> {
                         "INSERT INTO sys.Column( Table, Name, Type ) VALUES ({}, '{}', {})",
                         t.id, name, typ
                     ),
-                    AlterCol::Modify(name, typ) => format!(
-                        "UPDATE sys.Column SET Type = {} WHERE Table = {} AND Name = '{}'",
-                        typ, t.id, name
-                    ),
+                    AlterCol::Modify(name, typ) => {
+                        // The PRIMARY KEY position is kept.
+                        let old = t.info.typ[t.info.colmap[name]];
+                        format!(
+                            "UPDATE sys.Column SET Type = {} WHERE Table = {} AND Name = '{}'",
+                            typ | (old >> KEY_SHIFT << KEY_SHIFT),
+                            t.id,
+                            name
+                        )
+                    }
                     AlterCol::Drop(name) => format!("EXEC sys.DropColumn({},'{}')", t.id, name),
                 };
                 db.run(&sql, self.tr);
@@ -601,7 +1051,7 @@ impl<'r> EvalEnv<'r> {
 
             db.tables.borrow_mut().remove(name);
             db.tables.borrow_mut().insert(name.clone(), nt);
-            db.function_reset.set(true);
+            db.schema_changed();
         } else {
             panic!("ALTER TABLE not found {}", name.str());
         }
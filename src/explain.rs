@@ -0,0 +1,233 @@
+use crate::*;
+use Instruction::*;
+
+/// Note that rows of a table with a PRIMARY KEY are found by Id using a scan ( see [Table::id_get] ).
+fn id_scan(t: &Table) -> &'static str {
+    if t.info.key.is_empty() {
+        ""
+    } else {
+        " by scan"
+    }
+}
+
+/// Note the access path and residual WHERE condition of a compiled table expression.
+pub fn note_from(b: &Block, from: &Option<CTableExpression>, wher: &Option<Expr>) {
+    if b.explain.is_none() {
+        return;
+    }
+    let mut cols = SmallSet::default();
+    let from = match from {
+        Some(CTableExpression::IxRev(x)) => Some(&**x),
+        x => x.as_ref(),
+    };
+    match from {
+        Some(CTableExpression::Base(t)) => b.note(format!("scan {}", t.info.name.str())),
+        Some(CTableExpression::IdGet(t, _)) => {
+            cols.insert(usize::MAX);
+            b.note(format!("id lookup {}{}", t.info.name.str(), id_scan(t)));
+        }
+        Some(CTableExpression::IdList(t, keys)) => {
+            cols.insert(usize::MAX);
+            b.note(format!(
+                "id list ( {} ids ) {}{}",
+                keys.len(),
+                t.info.name.str(),
+                id_scan(t)
+            ));
+        }
+        Some(CTableExpression::IxList(t, keys, ix)) => {
+            let list = t.ixlist.borrow();
+            let ix = &list[*ix];
+            cols.insert(ix.cols[0]);
+            b.note(format!(
+                "index {} of {} key list ( {} ) {} keys",
+                sys::get_index_name(&b.db, ix.id),
+                t.info.name.str(),
+                ix.col_name(&t.info, ix.cols[0]),
+                keys.len()
+            ));
+        }
+        Some(CTableExpression::IxGet(t, keys, ix) | CTableExpression::IxCover(t, keys, ix)) => {
+            let list = t.ixlist.borrow();
+            let ix = &list[*ix];
+            let names: Vec<&str> = ix
+                .cols
+                .iter()
+                .take(keys.len())
+                .map(|c| {
+                    cols.insert(*c);
+                    ix.col_name(&t.info, *c)
+                })
+                .collect();
+            let cover = if let Some(CTableExpression::IxCover(..)) = from {
+                " covering"
+            } else {
+                ""
+            };
+            let key = if names.is_empty() {
+                String::new()
+            } else {
+                format!(" key ( {} )", names.join(", "))
+            };
+            b.note(format!(
+                "index {} of {}{}{}",
+                sys::get_index_name(&b.db, ix.id),
+                t.info.name.str(),
+                key,
+                cover
+            ));
+        }
+        Some(CTableExpression::KeyGet(t, keys)) => {
+            let names: Vec<&str> = t
+                .info
+                .key
+                .iter()
+                .take(keys.len())
+                .map(|c| {
+                    cols.insert(*c);
+                    &*t.info.colnames[*c]
+                })
+                .collect();
+            b.note(format!(
+                "primary key of {} key ( {} )",
+                t.info.name.str(),
+                names.join(", ")
+            ));
+        }
+        Some(CTableExpression::Values(_)) => b.note("values".to_string()),
+        Some(CTableExpression::IxRev(_)) | None => {}
+    }
+    if let Some(we) = wher {
+        if let Some(text) = residual(b, we, &mut cols) {
+            b.note(format!("where {}", text));
+        }
+    }
+}
+
+/// Get text of WHERE condition, excluding conditions satisfied by index key columns ( see table::get_keys ).
+fn residual(b: &Block, we: &Expr, cols: &mut SmallSet) -> Option<String> {
+    if let Some(col) = table::key_list_col(we) {
+        if cols.remove(col) {
+            return None;
+        }
+    }
+    if let ExprIs::Binary(Token::And, e1, e2) = &we.exp {
+        let x1 = residual(b, e1, cols);
+        let x2 = residual(b, e2, cols);
+        return match (x1, x2) {
+            (Some(x1), Some(x2)) => Some(format!("{} AND {}", x1, x2)),
+            (x1, None) => x1,
+            (None, x2) => x2,
+        };
+    }
+    Some(exp_text(b, we))
+}
+
+/// Get SQL text of an expression.
+pub fn exp_text(b: &Block, e: &Expr) -> String {
+    let list = |list: &[Expr]| {
+        let v: Vec<String> = list.iter().map(|e| exp_text(b, e)).collect();
+        v.join(", ")
+    };
+    match &e.exp {
+        ExprIs::Const(Value::String(s)) => format!("'{}'", s.replace('\'', "''")),
+        ExprIs::Const(Value::RcBinary(x)) => util::to_hex(x),
+        ExprIs::Const(x) => x.str().to_string(),
+        ExprIs::Local(x) => parse::tos(b.local_name(*x)).to_string(),
+        ExprIs::ColName(x) => x.to_string(),
+        ExprIs::Binary(op, e1, e2) => format!(
+            "{} {} {}",
+            operand_text(b, e1),
+            op_text(*op),
+            operand_text(b, e2)
+        ),
+        ExprIs::Not(x) => format!("NOT {}", operand_text(b, x)),
+        ExprIs::Minus(x) => format!("-{}", operand_text(b, x)),
+        ExprIs::Case(list, els) => {
+            let mut s = "CASE".to_string();
+            for (w, t) in list {
+                s += &format!(" WHEN {} THEN {}", exp_text(b, w), exp_text(b, t));
+            }
+            s + &format!(" ELSE {} END", exp_text(b, els))
+        }
+        ExprIs::FuncCall(name, args) => format!("{}( {} )", name.str(), list(args)),
+        ExprIs::BuiltinCall(name, args) => format!("{}( {} )", name, list(args)),
+        ExprIs::ScalarSelect(_) => "( SELECT .. )".to_string(),
+        ExprIs::List(x) => format!("( {} )", list(x)),
+    }
+}
+
+/// Get text of operand, binary expressions are enclosed in brackets.
+fn operand_text(b: &Block, e: &Expr) -> String {
+    if let ExprIs::Binary(..) = &e.exp {
+        format!("( {} )", exp_text(b, e))
+    } else {
+        exp_text(b, e)
+    }
+}
+
+/// Get text of a binary operator.
+fn op_text(op: Token) -> &'static str {
+    match op {
+        Token::Less => "<",
+        Token::LessEqual => "<=",
+        Token::GreaterEqual => ">=",
+        Token::Greater => ">",
+        Token::Equal => "=",
+        Token::NotEqual => "!=",
+        Token::In => "IN",
+        Token::Plus => "+",
+        Token::Minus => "-",
+        Token::Times => "*",
+        Token::Divide => "/",
+        Token::Percent => "%",
+        Token::VBar => "|",
+        Token::And => "AND",
+        Token::Or => "OR",
+        _ => "?",
+    }
+}
+
+/// Get text describing an instruction. Jump targets are relative to start.
+pub fn inst_text(b: &Block, inst: &Instruction, start: usize) -> String {
+    let jump = |x: &usize| {
+        let to = b.jump_location(*x);
+        if to == usize::MAX {
+            "?".to_string()
+        } else {
+            (to - start).to_string()
+        }
+    };
+    match inst {
+        PushConst(x) => format!("PushConst {}", x.str()),
+        PushValue(_) => "PushValue".to_string(),
+        PushLocal(x) => format!("PushLocal {}", parse::tos(b.local_name(*x))),
+        PopToLocal(x) => format!("PopToLocal {}", parse::tos(b.local_name(*x))),
+        Jump(x) => format!("Jump {}", jump(x)),
+        JumpIfFalse(x, _) => format!("JumpIfFalse {}", jump(x)),
+        Call(_) => "Call".to_string(),
+        Return => "Return".to_string(),
+        Throw => "Throw".to_string(),
+        Try(_) => "Try".to_string(),
+        Execute => "Execute".to_string(),
+        ForInit(_, _) => "ForInit".to_string(),
+        ForNext(x, _) => format!("ForNext {}", jump(x)),
+        ForSortInit(_, _) => "ForSortInit".to_string(),
+        ForSortNext(x, _) => format!("ForSortNext {}", jump(x)),
+        DataOp(x) => match &**x {
+            DO::Insert(t, _, _) => format!("Insert {}", t.info.name.str()),
+            DO::Update(_, te, _) => format!("Update {}", te.table().info.name.str()),
+            DO::Delete(te, _) => format!("Delete {}", te.table().info.name.str()),
+            _ => "DataOp".to_string(),
+        },
+        Select(_) => "Select".to_string(),
+        Set(_) => "Set".to_string(),
+        PushInt(_) => "PushInt".to_string(),
+        PushFloat(_) => "PushFloat".to_string(),
+        PushBool(_) => "PushBool".to_string(),
+        AssignLocal(x, _) => format!("AssignLocal {}", parse::tos(b.local_name(*x))),
+        AppendLocal(x, _) => format!("AppendLocal {}", parse::tos(b.local_name(*x))),
+        IncLocal(x, _) => format!("IncLocal {}", parse::tos(b.local_name(*x))),
+        DecLocal(x, _) => format!("DecLocal {}", parse::tos(b.local_name(*x))),
+    }
+}
@@ -1,5 +1,5 @@
 use crate::*;
-use Instruction::{DataOp, ForNext, ForSortNext, Jump, JumpIfFalse};
+use Instruction::{DataOp, ForNext, ForSortNext, Jump, JumpIfFalse, Try};
 
 /// Holds function name, line, column and message.
 #[derive(Clone)]
@@ -9,6 +9,7 @@ pub(crate) struct SqlError {
     pub column: usize,
     pub msg: String,
 }
+
 /// Table Expression ( not yet type-checked or compiled against database ).
 pub enum TableExpression {
     /// Base table.
@@ -95,6 +96,8 @@ pub enum Token {
     Number,
     /// Hex number.
     Hex,
+    /// Decimal number ( float literal ).
+    Decimal,
     /// String literal.
     String,
     /// (
@@ -220,10 +223,23 @@ pub enum DataKind {
     Bool = 5,
 }
 
-/// Low 3 (KBITS) bits are DataKind, rest is size in bytes.
+/// Low 3 (KBITS) bits are DataKind, next 8 (SBITS) bits are size in bytes, higher bits are column attributes.
 pub type DataType = usize;
 
 pub(crate) const KBITS: usize = 3;
+pub(crate) const SBITS: usize = 8;
+/// Column attribute : binary values are stored compressed.
+pub(crate) const COMPRESSED: DataType = 1 << (KBITS + SBITS);
+/// Column attribute : strings compare ignoring ASCII case ( COLLATE NOCASE ).
+pub(crate) const NOCASE: DataType = 2 << (KBITS + SBITS);
+/// Column attribute : strings compare ignoring case, using Unicode lower case mapping ( COLLATE CASEFOLD ).
+pub(crate) const CASEFOLD: DataType = 4 << (KBITS + SBITS);
+/// Mask for collation attributes.
+pub(crate) const COLLATION: DataType = NOCASE | CASEFOLD;
+/// Index column attribute : key values are stored in descending order.
+pub(crate) const DESC: DataType = 8 << (KBITS + SBITS);
+/// Column attribute : position ( from 1 ) of the column in the PRIMARY KEY is stored from this bit ( see [ColInfo::key] ).
+pub(crate) const KEY_SHIFT: usize = KBITS + SBITS + 4;
 pub(crate) const NONE: DataType = DataKind::None as usize;
 pub(crate) const BINARY: DataType = DataKind::Binary as usize + (16 << KBITS);
 pub(crate) const STRING: DataType = DataKind::String as usize + (16 << KBITS);
@@ -250,7 +266,7 @@ pub fn data_kind(x: DataType) -> DataKind {
 /// Compute the number of bytes required to store a value of the specified DataType.
 #[must_use]
 pub fn data_size(x: DataType) -> usize {
-    x >> KBITS
+    (x >> KBITS) & ((1 << SBITS) - 1)
 }
 
 /// Compilation block ( body of function or batch section ).
@@ -271,6 +287,12 @@ pub struct Block<'a> {
     pub from: Option<CTableExpression>,
     /// Only parse, no type checking or compilation.
     pub parse_only: bool,
+    /// Notes on compiled access paths ( EXPLAIN ), None if not required.
+    pub explain: Option<RefCell<Vec<String>>>,
+    /// Instruction index and source line of each statement.
+    pub lines: Vec<(usize, usize)>,
+    /// Column offsets in index records, when compiling for a covering index.
+    pub offsets: Option<Vec<usize>>,
     /// List of jumps.
     jumps: Vec<usize>,
     /// Lookup jump label by name.   
@@ -297,6 +319,9 @@ impl<'a> Block<'a> {
             from: None,
             db,
             parse_only: false,
+            explain: None,
+            lines: Vec::new(),
+            offsets: None,
         }
     }
 
@@ -312,6 +337,10 @@ impl<'a> Block<'a> {
                 JumpIfFalse(x, _) | Jump(x) | ForNext(x, _) | ForSortNext(x, _) => {
                     *x = self.jumps[*x]
                 }
+                Try(info) => {
+                    info.catch = self.jumps[info.catch];
+                    info.end = self.jumps[info.end];
+                }
                 _ => {}
             }
         }
@@ -368,6 +397,46 @@ impl<'a> Block<'a> {
         self.locals[num]
     }
 
+    /// Get the parameters ( local variables with names starting with '@' ) : names and types, local variable numbers.
+    /// Numbered parameters @1, @2 .. come first in number order, then named parameters in declaration order.
+    pub fn params(&self) -> (Vec<(String, DataType)>, Vec<usize>) {
+        let mut numbered = Vec::new();
+        let mut locals = Vec::new();
+        for x in 0..self.locals.len() {
+            let name = self.locals[x];
+            if let Some(num) = name.strip_prefix(b"@") {
+                match parse::tos(num).parse::<usize>() {
+                    Ok(n) => numbered.push((n, x)),
+                    Err(_) => locals.push(x),
+                }
+            }
+        }
+        numbered.sort();
+        for (i, (n, _)) in numbered.iter().enumerate() {
+            if *n != i + 1 {
+                panic!("parameter @{} not declared", i + 1);
+            }
+        }
+        let locals: Vec<usize> = numbered.iter().map(|(_, x)| *x).chain(locals).collect();
+        let names = locals
+            .iter()
+            .map(|x| (parse::tos(self.locals[*x]).to_string(), self.local_typ[*x]))
+            .collect();
+        (names, locals)
+    }
+
+    /// Add a note for EXPLAIN.
+    pub fn note(&self, s: String) {
+        if let Some(notes) = &self.explain {
+            notes.borrow_mut().push(s);
+        }
+    }
+
+    /// Get instruction location of jump id ( usize::MAX if not yet set ).
+    pub fn jump_location(&self, jump_id: usize) -> usize {
+        self.jumps[jump_id]
+    }
+
     /// Get a local jump id.
     pub fn get_jump_id(&mut self) -> usize {
         let result = self.jumps.len();
@@ -22,6 +22,9 @@ pub struct GenQuery {
     pub parts: Vec<Part>,
     /// Micro-seconds since January 1, 1970 0:00:00 UTC
     pub now: i64,
+    /// Seed for RANDOM and RANDOMINT builtin functions.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub seed: u64,
 }
 
 /// General Response.
@@ -46,6 +49,8 @@ pub struct GenTransaction {
     pub rp: GenResponse,
     /// Transaction extension data.
     pub ext: Box<dyn Any + Send + Sync>,
+    /// Random number state, initialised from qy.seed when first used.
+    rng: Option<u64>,
 }
 
 /// Part of multipart data ( uploaded files ).
@@ -84,6 +89,7 @@ impl GenTransaction {
                 cookies: BTreeMap::new(),
                 parts: Vec::new(),
                 now,
+                seed: now as u64,
             },
             rp: GenResponse {
                 err: String::new(),
@@ -92,6 +98,7 @@ impl GenTransaction {
                 headers,
             },
             ext: Box::new(()),
+            rng: None,
         }
     }
 
@@ -145,6 +152,11 @@ impl Transaction for GenTransaction {
         }
     }
 
+    fn random(&mut self) -> u64 {
+        let state = self.rng.get_or_insert(self.qy.seed);
+        crate::util::splitmix64(state)
+    }
+
     fn set_error(&mut self, err: String) {
         self.rp.err = err;
     }
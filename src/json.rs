@@ -0,0 +1,342 @@
+use crate::{util, Value};
+
+/// Parsed JSON value.
+#[non_exhaustive]
+pub enum Json {
+    /// null
+    Null,
+    /// true or false
+    Bool(bool),
+    /// Number ( source text ).
+    Number(String),
+    /// String.
+    String(String),
+    /// Array.
+    Array(Vec<Json>),
+    /// Object ( name, value ) pairs in source order.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parse JSON text.
+    pub fn parse(s: &str) -> Json {
+        let mut p = JsonParser {
+            s: s.as_bytes(),
+            ix: 0,
+        };
+        let result = p.value(0);
+        p.space();
+        if p.ix != p.s.len() {
+            panic!("invalid JSON: unexpected characters after value");
+        }
+        result
+    }
+
+    /// Find the value selected by path, e.g. `$.customer.orders[0].id`.
+    pub fn get(&self, path: &str) -> Option<&Json> {
+        let p = path.as_bytes();
+        if p.first() != Some(&b'$') {
+            panic!("JSON path must start with $");
+        }
+        let mut ix = 1;
+        let mut cur = self;
+        while ix < p.len() {
+            match p[ix] {
+                b'.' => {
+                    ix += 1;
+                    let name = if p.get(ix) == Some(&b'"') {
+                        let start = ix + 1;
+                        ix = start;
+                        while ix < p.len() && p[ix] != b'"' {
+                            ix += 1;
+                        }
+                        if ix == p.len() {
+                            panic!("invalid JSON path: missing closing quote");
+                        }
+                        ix += 1;
+                        &path[start..ix - 1]
+                    } else {
+                        let start = ix;
+                        while ix < p.len() && p[ix] != b'.' && p[ix] != b'[' {
+                            ix += 1;
+                        }
+                        &path[start..ix]
+                    };
+                    match cur {
+                        Json::Object(list) => {
+                            cur = &list.iter().find(|(n, _)| n == name)?.1;
+                        }
+                        _ => return None,
+                    }
+                }
+                b'[' => {
+                    let start = ix + 1;
+                    while ix < p.len() && p[ix] != b']' {
+                        ix += 1;
+                    }
+                    if ix == p.len() {
+                        panic!("invalid JSON path: missing ]");
+                    }
+                    let n: usize = path[start..ix]
+                        .trim()
+                        .parse()
+                        .expect("invalid JSON path: bad array index");
+                    ix += 1;
+                    match cur {
+                        Json::Array(list) => cur = list.get(n)?,
+                        _ => return None,
+                    }
+                }
+                _ => panic!("invalid JSON path"),
+            }
+        }
+        Some(cur)
+    }
+
+    /// Append JSON text for value to s.
+    pub fn write(&self, s: &mut String) {
+        match self {
+            Json::Null => s.push_str("null"),
+            Json::Bool(b) => s.push_str(if *b { "true" } else { "false" }),
+            Json::Number(n) => s.push_str(n),
+            Json::String(x) => push_string(s, x),
+            Json::Array(list) => {
+                s.push('[');
+                for (i, v) in list.iter().enumerate() {
+                    if i > 0 {
+                        s.push(',');
+                    }
+                    v.write(s);
+                }
+                s.push(']');
+            }
+            Json::Object(list) => {
+                s.push('{');
+                for (i, (n, v)) in list.iter().enumerate() {
+                    if i > 0 {
+                        s.push(',');
+                    }
+                    push_string(s, n);
+                    s.push(':');
+                    v.write(s);
+                }
+                s.push('}');
+            }
+        }
+    }
+}
+
+/// Append s to result with JSON escapes ( without enclosing quotes ).
+pub fn push_escaped(result: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            '\u{8}' => result.push_str("\\b"),
+            '\u{c}' => result.push_str("\\f"),
+            c if (c as u32) < 0x20 => {
+                result.push_str(&format!("\\u{:04x}", c as u32));
+            }
+            c => result.push(c),
+        }
+    }
+}
+
+/// Append s to result as a quoted JSON string.
+pub fn push_string(result: &mut String, s: &str) {
+    result.push('"');
+    push_escaped(result, s);
+    result.push('"');
+}
+
+/// Append JSON representation of value to result. Binary values are written as hex strings.
+pub fn push_value(result: &mut String, v: &Value) {
+    match v {
+        Value::Int(x) => result.push_str(&x.to_string()),
+        Value::Float(x) if x.is_finite() => result.push_str(&x.to_string()),
+        Value::Float(_) => result.push_str("null"),
+        Value::Bool(x) => result.push_str(if *x { "true" } else { "false" }),
+        Value::String(x) => push_string(result, x),
+        Value::RcBinary(x) => push_string(result, &util::to_hex(x)),
+        Value::ArcBinary(x) => push_string(result, &util::to_hex(x)),
+        _ => result.push_str("null"),
+    }
+}
+
+/// Maximum nesting of JSON arrays and objects ( deeper text could overflow the stack ).
+const MAX_DEPTH: usize = 512;
+
+/// Recursive descent JSON parser.
+struct JsonParser<'a> {
+    s: &'a [u8],
+    ix: usize,
+}
+
+impl JsonParser<'_> {
+    fn space(&mut self) {
+        while self.ix < self.s.len() && matches!(self.s[self.ix], b' ' | b'\t' | b'\n' | b'\r') {
+            self.ix += 1;
+        }
+    }
+
+    fn next(&mut self) -> u8 {
+        self.space();
+        if self.ix == self.s.len() {
+            panic!("invalid JSON: unexpected end of text");
+        }
+        self.ix += 1;
+        self.s[self.ix - 1]
+    }
+
+    fn peek(&mut self) -> u8 {
+        self.space();
+        if self.ix == self.s.len() {
+            0
+        } else {
+            self.s[self.ix]
+        }
+    }
+
+    fn literal(&mut self, lit: &[u8], result: Json) -> Json {
+        if !self.s[self.ix - 1..].starts_with(lit) {
+            panic!("invalid JSON: unexpected character");
+        }
+        self.ix += lit.len() - 1;
+        result
+    }
+
+    /// Parse a value, depth is the number of enclosing arrays and objects.
+    fn value(&mut self, depth: usize) -> Json {
+        if depth == MAX_DEPTH {
+            panic!("invalid JSON: nesting too deep");
+        }
+        match self.next() {
+            b'{' => {
+                let mut list = Vec::new();
+                if self.peek() == b'}' {
+                    self.ix += 1;
+                } else {
+                    loop {
+                        if self.next() != b'"' {
+                            panic!("invalid JSON: expected name");
+                        }
+                        let name = self.string();
+                        if self.next() != b':' {
+                            panic!("invalid JSON: expected colon");
+                        }
+                        list.push((name, self.value(depth + 1)));
+                        match self.next() {
+                            b',' => {}
+                            b'}' => break,
+                            _ => panic!("invalid JSON: expected comma or }}"),
+                        }
+                    }
+                }
+                Json::Object(list)
+            }
+            b'[' => {
+                let mut list = Vec::new();
+                if self.peek() == b']' {
+                    self.ix += 1;
+                } else {
+                    loop {
+                        list.push(self.value(depth + 1));
+                        match self.next() {
+                            b',' => {}
+                            b']' => break,
+                            _ => panic!("invalid JSON: expected comma or ]"),
+                        }
+                    }
+                }
+                Json::Array(list)
+            }
+            b'"' => Json::String(self.string()),
+            b't' => self.literal(b"true", Json::Bool(true)),
+            b'f' => self.literal(b"false", Json::Bool(false)),
+            b'n' => self.literal(b"null", Json::Null),
+            b'-' | b'0'..=b'9' => {
+                let start = self.ix - 1;
+                while self.ix < self.s.len()
+                    && matches!(
+                        self.s[self.ix],
+                        b'0'..=b'9' | b'.' | b'e' | b'E' | b'+' | b'-'
+                    )
+                {
+                    self.ix += 1;
+                }
+                let n = std::str::from_utf8(&self.s[start..self.ix]).unwrap();
+                if n.parse::<f64>().is_err() {
+                    panic!("invalid JSON: bad number");
+                }
+                Json::Number(n.to_string())
+            }
+            _ => panic!("invalid JSON: unexpected character"),
+        }
+    }
+
+    /// Read string ( opening quote already read ).
+    fn string(&mut self) -> String {
+        let mut result = Vec::new();
+        loop {
+            if self.ix >= self.s.len() {
+                panic!("invalid JSON: unterminated string");
+            }
+            let c = self.s[self.ix];
+            self.ix += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let e = self.s.get(self.ix).copied().unwrap_or(0);
+                    self.ix += 1;
+                    let c = match e {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut u = self.hex4();
+                            if (0xd800..0xdc00).contains(&u)
+                                && self.s[self.ix..].starts_with(b"\\u")
+                            {
+                                self.ix += 2;
+                                let lo = self.hex4();
+                                u = 0x10000
+                                    + ((u - 0xd800) << 10)
+                                    + (lo.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            char::from_u32(u).unwrap_or('\u{fffd}')
+                        }
+                        _ => panic!("invalid JSON: bad escape"),
+                    };
+                    let mut buf = [0; 4];
+                    result.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                _ => result.push(c),
+            }
+        }
+        String::from_utf8(result).unwrap()
+    }
+
+    fn hex4(&mut self) -> u32 {
+        if self.ix + 4 > self.s.len()
+            || !self.s[self.ix..self.ix + 4]
+                .iter()
+                .all(u8::is_ascii_hexdigit)
+        {
+            panic!("invalid JSON: bad unicode escape");
+        }
+        let mut u = 0;
+        for c in &self.s[self.ix..self.ix + 4] {
+            u = u * 16 + util::hex(*c) as u32;
+        }
+        self.ix += 4;
+        u
+    }
+}
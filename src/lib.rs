@@ -32,7 +32,7 @@
 //! - `table` : Allow direct access to database tables.
 //! - `max` : maximal interface, including internal modules (which may not be stable).
 //! - `verify` : Allows database structure to be verified using builtin function VERIFYDB.
-//! - `pack` : Allows database pages to be packed using builtin function REPACKFILE.
+//! - `pack` : Allows database pages to be packed using builtin functions REPACKFILE and REPACKDB.
 //! - `renumber` : Allows database pages to be renumbered using builtin function RENUMBER, eliminating free pages.
 //! - `unsafe-optim` : Enable unsafe optimisations in release mode.
 //! - `log` : Log "interesting" information about database operation (helps give an idea what is happening).
@@ -51,7 +51,7 @@
 //!
 //! - Index storage - an index record refers back to the main table using the 64-bit Id.
 //!
-//! When a page becomes too big, it is split into two pages.
+//! When a page becomes too big, it is split into two pages. If keys are being inserted in order, the split is uneven, so pages stay nearly full.
 //!
 //! Each page is implemented as a binary tree ( so there is a tree of trees ).
 //!
@@ -101,6 +101,7 @@ pub use crate::{
     blockpagestg::BlockPageStg,
     builtin::standard_builtins,
     pstore::{AccessPagedData, SharedPagedData},
+    run::{Prepared, RunLimits},
     stg::{
         DummyFile, MemFile, MultiFileStorage, PageStorage, PageStorageInfo, SimpleFileStorage,
         Storage,
@@ -135,8 +136,11 @@ use crate::{
     page::{Page, PagePtr},
     parse::Parser,
     run::*,
-    sortedfile::{Asc, Id, Record, SortedFile},
-    table::{ColInfo, IndexInfo, Row, SaveOp, Table},
+    sortedfile::{Asc, FileState, Id, Record, SortedFile},
+    table::{
+        ColInfo, FilterCond, IndexExp, IndexInfo, IndexStats, Row, SaveOp, Table, TableState,
+        EXP_COL,
+    },
     util::{nd, newmap, SmallSet},
     value::*,
 };
@@ -299,12 +303,55 @@ pub mod bytes;
 #[cfg(not(feature = "max"))]
 mod bytes;
 
+#[cfg(feature = "builtin")]
+/// Digest functions ( SHA-256, SHA-1, MD5, HMAC-SHA256 ).
+pub mod hash;
+#[cfg(not(feature = "builtin"))]
+mod hash;
+
+#[cfg(feature = "builtin")]
+/// JSON parsing and output ( JSON builtin functions and SELECT .. FOR JSON ).
+pub mod json;
+#[cfg(not(feature = "builtin"))]
+mod json;
+
+#[cfg(feature = "builtin")]
+/// Data compression ( COMPRESS and DECOMPRESS builtin functions, COMPRESSED columns ).
+pub mod compress;
+#[cfg(not(feature = "builtin"))]
+mod compress;
+
+#[cfg(feature = "max")]
+/// EXPLAIN statement : descriptions of access paths and instructions.
+pub mod explain;
+#[cfg(not(feature = "max"))]
+mod explain;
+
+#[cfg(feature = "max")]
+/// Execution profiling : statistics for statements and functions, slow statement log.
+pub mod profile;
+#[cfg(not(feature = "max"))]
+mod profile;
+
+#[cfg(feature = "max")]
+/// Triggers : FN bodies executed after rows of a table are inserted, updated or deleted.
+pub mod trigger;
+#[cfg(not(feature = "max"))]
+mod trigger;
+
+#[cfg(feature = "max")]
+/// SAVEPOINT, ROLLBACK TO and other transaction statements.
+pub mod savepoint;
+#[cfg(not(feature = "max"))]
+mod savepoint;
+
 // End of modules.
 
 /// ```Arc<Vec<u8>>```
 pub type Data = Arc<Vec<u8>>;
 
 /// Mutable Data, copied on write.
+#[derive(Clone)]
 pub struct MData(Data);
 
 impl MData {
@@ -377,8 +424,28 @@ pub struct Database {
     bs: Vec<ByteStorage>,
     /// Flag to reset the functions cache after save.
     pub function_reset: Cell<bool>,
+    /// Incremented when the schema changes ( prepared statements are then recompiled ).
+    schema_gen: Cell<u64>,
+    /// Profiler ( if profiling is enabled ).
+    profiler: RefCell<Option<profile::Profiler>>,
+    /// Limits on the execution of a batch.
+    limits: RefCell<RunLimits>,
+    /// Budget of the batch being executed ( if there are limits ).
+    pub(crate) budget: RefCell<Option<Rc<exec::Budget>>>,
+    /// Savepoints recorded since last save.
+    savepoints: RefCell<Vec<savepoint::Savepoint>>,
+    /// Pages allocated since the first savepoint.
+    allocated: RefCell<Vec<u64>>,
+    /// Pages freed since the first savepoint ( these are freed when the database is saved ).
+    freed: RefCell<Vec<u64>>,
     /// Maximum size of logical page.
     page_size_max: usize,
+    /// Rows added to indexes being built ONLINE each time the database is saved ( see [Database::set_build_rows] ).
+    build_rows: Cell<usize>,
+    #[cfg(feature = "pack")]
+    /// Where the next call to REPACKDB starts : ( table id, file, number of level 1 pages already passed ).
+    /// Table id i64::MAX means every file has been repacked, and pages are renumbered next.
+    repack_next: Cell<(i64, usize, usize)>,
 
     bpf: [usize; bytes::NFT],
 }
@@ -409,13 +476,13 @@ impl Database {
             "Function",
             &[("Schema", INT), ("Name", NAMESTR), ("Def", BIGSTR)],
         );
-        sys_schema.add_index(tb.rt(), vec![0], 1);
-        sys_table.add_index(tb.rt(), vec![1, 2], 2);
-        sys_column.add_index(tb.rt(), vec![0], 3);
-        sys_index.add_index(tb.rt(), vec![1], 4);
-        sys_index_col.add_index(tb.rt(), vec![0], 5);
-        sys_function.add_index(tb.rt(), vec![0, 1], 6);
-        sys_function.add_index(tb.rt(), vec![1], 7);
+        sys_schema.add_index(tb.rt(), vec![0], Vec::new(), 1);
+        sys_table.add_index(tb.rt(), vec![1, 2], Vec::new(), 2);
+        sys_column.add_index(tb.rt(), vec![0], Vec::new(), 3);
+        sys_index.add_index(tb.rt(), vec![1], Vec::new(), 4);
+        sys_index_col.add_index(tb.rt(), vec![0], Vec::new(), 5);
+        sys_function.add_index(tb.rt(), vec![0, 1], Vec::new(), 6);
+        sys_function.add_index(tb.rt(), vec![1], Vec::new(), 7);
 
         let page_size_max = apd.spd.psi.max_size_page();
 
@@ -440,10 +507,20 @@ impl Database {
             tables: newmap(),
             builtins,
             function_reset: Cell::new(false),
+            schema_gen: Cell::new(0),
+            profiler: RefCell::new(None),
+            limits: RefCell::new(RunLimits::default()),
+            budget: RefCell::new(None),
+            savepoints: RefCell::new(Vec::new()),
+            allocated: RefCell::new(Vec::new()),
+            freed: RefCell::new(Vec::new()),
             lastid: Cell::new(0),
             err: Cell::new(false),
             is_new,
             page_size_max,
+            build_rows: Cell::new(1000),
+            #[cfg(feature = "pack")]
+            repack_next: Cell::new((0, 0, 0)),
             bpf,
         });
 
@@ -493,42 +570,242 @@ GO
 
     /// Run a batch of SQL.
     pub fn run(self: &DB, source: &str, tr: &mut dyn Transaction) {
-        if let Some(e) = self.go(source, tr) {
-            let err = format!(
-                "{} in {} at line {} column {}.",
-                e.msg, e.rname, e.line, e.column
-            );
-            tr.set_error(err);
-            self.err.set(true);
+        if let Some(e) = self.go(source, None, tr) {
+            self.set_error(e, tr);
         }
     }
 
-    /// Run a batch of SQL.
-    fn go(self: &DB, source: &str, tr: &mut dyn Transaction) -> Option<SqlError> {
+    /// Run a batch of SQL, result is the elapsed time.
+    pub fn run_timed(self: &DB, source: &str, tr: &mut dyn Transaction) -> std::time::Duration {
+        let start = std::time::Instant::now();
+        self.run(source, tr);
+        start.elapsed()
+    }
+
+    /// Enable profiling ( or disable if p is None ). Result is the previous profiler, which holds the collected statistics.
+    pub fn set_profiler(self: &DB, p: Option<profile::Profiler>) -> Option<profile::Profiler> {
+        std::mem::replace(&mut *self.profiler.borrow_mut(), p)
+    }
+
+    /// Set the limits on the execution of a batch. Result is the previous limits.
+    pub fn set_limits(self: &DB, limits: RunLimits) -> RunLimits {
+        std::mem::replace(&mut *self.limits.borrow_mut(), limits)
+    }
+
+    /// Set the number of rows added to indexes being built ONLINE each time the database is saved ( default 1000 ).
+    /// Zero means builds only progress when BUILDINDEX is called. Result is the previous setting.
+    pub fn set_build_rows(self: &DB, rows: usize) -> usize {
+        self.build_rows.replace(rows)
+    }
+
+    /// Start a budget for a batch if there are limits and no batch is already executing ( EXECUTE runs nested batches ).
+    /// Result is true if a budget was started.
+    fn begin_budget(self: &DB) -> bool {
+        let limits = self.limits.borrow();
+        let mut budget = self.budget.borrow_mut();
+        if budget.is_some() || limits.is_unlimited() {
+            return false;
+        }
+        *budget = Some(Rc::new(exec::Budget::new(limits.clone())));
+        true
+    }
+
+    /// Run a batch of SQL. The args are assigned to the parameters ( local variables declared in the first section with names starting with '@' ),
+    /// numbered parameters @1, @2 .. first, then named parameters in declaration order.
+    pub fn run_with(self: &DB, source: &str, args: &[Value], tr: &mut dyn Transaction) {
+        if let Some(e) = self.go(source, Some(args), tr) {
+            self.set_error(e, tr);
+        }
+    }
+
+    /// Run a batch of SQL, result is a ResultSet for each SELECT, or an error message.
+    pub fn query(self: &DB, source: &str) -> Result<Vec<ResultSet>, String> {
+        let mut tr = QueryTransaction::default();
+        self.run(source, &mut tr);
+        if tr.err.is_empty() {
+            Ok(tr.results)
+        } else {
+            Err(tr.err)
+        }
+    }
+
+    /// Report an error to the transaction.
+    fn set_error(self: &DB, e: SqlError, tr: &mut dyn Transaction) {
+        let err = format!(
+            "{} in {} at line {} column {}.",
+            e.msg, e.rname, e.line, e.column
+        );
+        tr.set_error(err);
+        self.err.set(true);
+    }
+
+    /// Run a batch of SQL, result is the error ( if any ).
+    pub(crate) fn go(
+        self: &DB,
+        source: &str,
+        args: Option<&[Value]>,
+        tr: &mut dyn Transaction,
+    ) -> Option<SqlError> {
+        let top = self.begin_budget();
         let mut p = Parser::new(source, self);
         let result = std::panic::catch_unwind(panic::AssertUnwindSafe(|| {
-            p.batch(tr);
+            p.batch_with(tr, args);
         }));
+        if top {
+            *self.budget.borrow_mut() = None;
+        }
         if let Err(x) = result {
-            Some(if let Some(e) = x.downcast_ref::<SqlError>() {
-                SqlError {
-                    msg: e.msg.clone(),
-                    line: e.line,
-                    column: e.column,
-                    rname: e.rname.clone(),
-                }
-            } else if let Some(s) = x.downcast_ref::<&str>() {
-                p.make_error((*s).to_string())
-            } else if let Some(s) = x.downcast_ref::<String>() {
-                p.make_error(s.to_string())
-            } else {
-                p.make_error("unrecognised/unexpected error".to_string())
-            })
+            Some(sql_error(x, |msg| p.make_error(msg)))
         } else {
             None
         }
     }
 
+    /// Compile a batch of SQL for repeated execution by [Database::run_prepared].
+    /// The first section is compiled immediately, later sections ( after GO ) are compiled when first executed.
+    /// Result is an error message if the first section cannot be compiled.
+    pub fn prepare(self: &DB, source: &str) -> Result<Prepared, String> {
+        match self.compile_section(source, 0) {
+            Ok((s, params, count)) => {
+                let mut sections = vec![None; count];
+                sections[0] = Some(Rc::new(s));
+                Ok(Prepared {
+                    source: source.to_string(),
+                    params,
+                    sections: RefCell::new(sections),
+                    generation: Cell::new(self.schema_gen.get()),
+                    db: RefCell::new(Rc::downgrade(self)),
+                })
+            }
+            Err(e) => Err(format!(
+                "{} in {} at line {} column {}.",
+                e.msg, e.rname, e.line, e.column
+            )),
+        }
+    }
+
+    /// Run a batch compiled by [Database::prepare]. The args are assigned to the parameters in order.
+    /// Sections compiled before a schema change, or for a different database, are recompiled.
+    pub fn run_prepared(self: &DB, p: &Prepared, args: &[Value], tr: &mut dyn Transaction) {
+        let top = self.begin_budget();
+        let e = self.go_prepared(p, args, tr);
+        if top {
+            *self.budget.borrow_mut() = None;
+        }
+        if let Some(e) = e {
+            self.set_error(e, tr);
+        }
+    }
+
+    /// Run a prepared batch, result is the error ( if any ).
+    fn go_prepared(
+        self: &DB,
+        p: &Prepared,
+        args: &[Value],
+        tr: &mut dyn Transaction,
+    ) -> Option<SqlError> {
+        let mut n = 0;
+        while n < p.sections.borrow().len() {
+            if p.generation.get() != self.schema_gen.get()
+                || p.db.borrow().as_ptr() != Rc::as_ptr(self)
+            {
+                p.generation.set(self.schema_gen.get());
+                *p.db.borrow_mut() = Rc::downgrade(self);
+                for s in p.sections.borrow_mut().iter_mut() {
+                    *s = None;
+                }
+            }
+            let s = p.sections.borrow()[n].clone();
+            let s = match s {
+                Some(s) => s,
+                None => match self.compile_section(&p.source, n) {
+                    Ok((s, _, _)) => {
+                        let s = Rc::new(s);
+                        p.sections.borrow_mut()[n] = Some(s.clone());
+                        s
+                    }
+                    Err(e) => return Some(e),
+                },
+            };
+            // Instruction being executed, None until the args are bound.
+            let mut ip = None;
+            let result = std::panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                let mut ee = EvalEnv::new(self.clone(), tr);
+                ee.alloc_locals(&s.local_typ, 0);
+                if n == 0 {
+                    ee.bind(&p.params, &s.params, args);
+                }
+                let ip = ip.insert(0);
+                if ee.profile {
+                    let start = profile::Start::new(&ee);
+                    ee.go_profiled(&s.ilist, &s.lines, "batch", &p.source, ip);
+                    profile::record_batch(self, &start.stats(&ee));
+                } else {
+                    ee.go_statements(&s.ilist, &s.lines, ip);
+                }
+            }));
+            if let Err(x) = result {
+                // Runtime errors are reported at the start of the line of the failing statement.
+                let (line, column) = match ip {
+                    Some(ip) => match s.lines.iter().rev().find(|(i, _)| *i <= ip) {
+                        Some((_, line)) => (*line, 1),
+                        None => s.end,
+                    },
+                    None => s.end,
+                };
+                return Some(sql_error(x, |msg| SqlError {
+                    msg,
+                    line,
+                    column,
+                    rname: "batch".to_string(),
+                }));
+            }
+            n += 1;
+        }
+        None
+    }
+
+    /// Compile section n of a batch.
+    /// Result is the compiled section, the section parameters ( name and type ) and the number of sections.
+    #[allow(clippy::type_complexity)]
+    fn compile_section(
+        self: &DB,
+        source: &str,
+        n: usize,
+    ) -> Result<(Section, Vec<(String, DataType)>, usize), SqlError> {
+        let mut p = Parser::new(source, self);
+        let result = std::panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let count = p.compile_section(n);
+            // Only the first section has parameters.
+            let params = if n == 0 {
+                p.b.params()
+            } else {
+                Default::default()
+            };
+            (count, params)
+        }));
+        match result {
+            Ok((count, (names, params))) => {
+                let b = &mut p.b;
+                let section = Section {
+                    ilist: std::mem::take(&mut b.ilist),
+                    local_typ: std::mem::take(&mut b.local_typ),
+                    params,
+                    lines: std::mem::take(&mut b.lines),
+                    end: p.section_end,
+                };
+                Ok((section, names, count))
+            }
+            Err(x) => Err(sql_error(x, |msg| p.make_error(msg))),
+        }
+    }
+
+    /// Note a schema change : the functions cache is reset on save and prepared statements are recompiled.
+    pub(crate) fn schema_changed(self: &DB) {
+        self.function_reset.set(true);
+        self.schema_gen.set(self.schema_gen.get() + 1);
+    }
+
     /// Test whether there are unsaved changes.
     pub fn changed(self: &DB) -> bool {
         if self.err.get() {
@@ -555,6 +832,8 @@ GO
     pub fn save(self: &DB) -> usize {
         let op = if self.err.get() {
             self.err.set(false);
+            // Tables and functions may have been created or altered.
+            self.schema_gen.set(self.schema_gen.get() + 1);
             SaveOp::RollBack
         } else {
             SaveOp::Save
@@ -562,8 +841,8 @@ GO
         for bs in &self.bs {
             bs.save(self, op);
         }
-        let tm = &*self.tables.borrow();
-        for t in tm.values() {
+        let tables: Vec<Rc<Table>> = self.tables.borrow().values().cloned().collect();
+        for t in &tables {
             if t.id_gen_dirty.get() {
                 if op == SaveOp::Save {
                     sys::save_id_gen(self, t.id as u64, t.id_gen.get().unwrap());
@@ -573,17 +852,71 @@ GO
                 t.id_gen_dirty.set(false);
             }
         }
-        for t in tm.values() {
+        for t in &tables {
             t.save(self, op);
         }
         if self.function_reset.get() {
             for function in self.functions.borrow().values() {
-                function.ilist.borrow_mut().clear();
+                // The function may be executing ( COMMIT statement ).
+                if let Ok(mut ilist) = function.ilist.try_borrow_mut() {
+                    ilist.clear();
+                }
             }
             self.functions.borrow_mut().clear();
             self.function_reset.set(false);
         }
-        self.apd.save(op)
+        self.savepoints.borrow_mut().clear();
+        self.allocated.borrow_mut().clear();
+        let freed = std::mem::take(&mut *self.freed.borrow_mut());
+        if op == SaveOp::Save {
+            for lpnum in freed {
+                self.apd.free_page(lpnum);
+            }
+        }
+        let mut result = self.apd.save(op);
+        if op == SaveOp::RollBack {
+            // Index build progress is held in memory, reload it from sys.IndexBuild.
+            for t in &tables {
+                sys::reload_index_builds(self, t);
+            }
+            // Repacking that was rolled back needs to be done again.
+            #[cfg(feature = "pack")]
+            self.repack_next.set((0, 0, 0));
+        } else if self.build_rows.get() > 0 {
+            result += self.build_step();
+        }
+        result
+    }
+
+    /// Add rows to indexes being built ONLINE, then save. Result is the number of logical pages that were updated.
+    /// A step that fails is rolled back ( BUILDINDEX can be used to see the error ).
+    fn build_step(self: &DB) -> usize {
+        let tables = sys::get_index_build_tables(self);
+        if tables.is_empty() {
+            return 0;
+        }
+        // The save below must not start another step.
+        let budget = self.build_rows.replace(0);
+        let mut tr = QueryTransaction::default();
+        let result = std::panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let mut ee = EvalEnv::new(self.clone(), &mut tr);
+            let mut rows = budget;
+            for name in &tables {
+                while rows > 0 {
+                    let n = ee.build_index(name, rows);
+                    if n <= 0 {
+                        break;
+                    }
+                    rows -= n as usize;
+                }
+            }
+        }));
+        if result.is_err() {
+            self.err.set(true);
+        }
+        let pages = self.save();
+        self.build_rows.set(budget);
+        pages
     }
 
     #[cfg(not(feature = "table"))]
@@ -663,12 +996,20 @@ GO
 
     /// Allocate a page of underlying file storage.
     fn alloc_page(self: &DB) -> u64 {
-        self.apd.alloc_page()
+        let lpnum = self.apd.alloc_page();
+        if !self.savepoints.borrow().is_empty() {
+            self.allocated.borrow_mut().push(lpnum);
+        }
+        lpnum
     }
 
-    /// Free a page of underlying file storage.
+    /// Free a page of underlying file storage ( deferred until save if there is a savepoint ).
     fn free_page(self: &DB, lpnum: u64) {
-        self.apd.free_page(lpnum);
+        if self.savepoints.borrow().is_empty() {
+            self.apd.free_page(lpnum);
+        } else {
+            self.freed.borrow_mut().push(lpnum);
+        }
     }
 
     #[cfg(feature = "pack")]
@@ -680,20 +1021,68 @@ GO
     #[cfg(feature = "pack")]
     /// Repack the specified sortedfile.
     fn repack_file(self: &DB, k: i64, schema: &str, tname: &str) -> i64 {
+        let mut b = sortedfile::RepackBudget::new(sortedfile::REPACK_LIMIT, i64::MAX);
         if k >= 0 {
             let name = ObjRef::new(schema, tname);
             if let Some(t) = self.get_table(&name) {
-                return t.repack(self, k as usize);
+                return t.repack(self, k as usize, &mut b);
             }
         } else {
             let k = (-k - 1) as usize;
             if k < 4 {
-                return self.bs[k].repack_file(self);
+                return self.bs[k].repack_file(self, &mut b);
             }
         }
         -1
     }
 
+    #[cfg(feature = "pack")]
+    /// Repack the files of the database in turn, continuing from where the previous call stopped,
+    /// until budget pages have been visited. Result is the number of pages freed.
+    /// When every file has been repacked, the next call renumbers pages ( if the renumber feature is enabled )
+    /// and returns -1, after which repacking starts again from the first file.
+    /// Only loaded tables are repacked ( REPACKDB loads all tables first ).
+    fn repack_db(self: &DB, budget: i64) -> i64 {
+        let start = self.repack_next.get();
+        if start.0 == i64::MAX {
+            #[cfg(feature = "renumber")]
+            self.renumber();
+            self.repack_next.set((0, 0, 0));
+            return -1;
+        }
+        // Files are identified by ( table id, k ), table id zero is used for byte storage.
+        let mut files: Vec<(i64, usize)> = (0..self.bs.len()).map(|k| (0, k)).collect();
+        let mut tables = HashMap::default();
+        for t in self.tables.borrow().values() {
+            for k in 0..=t.ixlist.borrow().len() {
+                files.push((t.id, k));
+            }
+            tables.insert(t.id, t.clone());
+        }
+        files.sort();
+        let mut b = sortedfile::RepackBudget::new(i64::MAX, budget);
+        for (id, k) in files.into_iter().filter(|f| *f >= (start.0, start.1)) {
+            b.skip = if (id, k) == (start.0, start.1) {
+                start.2
+            } else {
+                0
+            };
+            b.passed = 0;
+            if id == 0 {
+                self.bs[k].repack_file(self, &mut b);
+            } else {
+                tables[&id].repack(self, k, &mut b);
+            }
+            if b.done() {
+                // The file may have more pages to repack, so carry on from where it stopped next time.
+                self.repack_next.set((id, k, b.passed));
+                return b.freed;
+            }
+        }
+        self.repack_next.set((i64::MAX, 0, 0));
+        b.freed
+    }
+
     #[cfg(feature = "verify")]
     /// Verify the page structure of the database.
     pub fn verify(self: &DB) -> String {
@@ -761,6 +1150,19 @@ impl Drop for Database {
     }
 }
 
+/// Get SqlError from a panic payload, make is used to construct the error from a message.
+fn sql_error(x: Box<dyn Any + Send>, make: impl FnOnce(String) -> SqlError) -> SqlError {
+    if let Some(e) = x.downcast_ref::<SqlError>() {
+        e.clone()
+    } else if let Some(s) = x.downcast_ref::<&str>() {
+        make((*s).to_string())
+    } else if let Some(s) = x.downcast_ref::<String>() {
+        make(s.to_string())
+    } else {
+        make("unrecognised/unexpected error".to_string())
+    }
+}
+
 /// For creating system tables.
 struct TableBuilder {
     alloc: usize,
@@ -799,6 +1201,12 @@ pub trait Transaction: Any {
     /// HEADER builtin function, adds header to response.
     fn header(&mut self, _name: &str, _value: &str) {}
 
+    /// Called at the start of a SELECT, before the rows are passed to selected.
+    fn begin_select(&mut self, _colnames: &[String], _kinds: &[DataKind]) {}
+
+    /// Called at the end of a SELECT with the number of rows passed to selected ( not called if there is an error ).
+    fn end_select(&mut self, _rowcount: usize) {}
+
     /// Append SELECT values to response body.
     fn selected(&mut self, values: &[Value]);
 
@@ -822,6 +1230,20 @@ pub trait Transaction: Any {
         nd()
     }
 
+    /// Get a random number, used by RANDOM and RANDOMINT builtin functions.
+    /// Override to make results repeatable ( e.g. for testing or replication ).
+    fn random(&mut self) -> u64 {
+        use std::hash::{BuildHasher, Hasher};
+        let mut h = std::collections::hash_map::RandomState::new().build_hasher();
+        h.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos(),
+        );
+        h.finish()
+    }
+
     /// Set the error string.
     fn set_error(&mut self, err: String);
 
@@ -840,7 +1262,7 @@ pub trait Transaction: Any {
 }
 
 /// [Transaction] where output is discarded (used for initialisation ).
-struct DummyTransaction {}
+pub(crate) struct DummyTransaction {}
 impl Transaction for DummyTransaction {
     fn selected(&mut self, _values: &[Value]) {}
     /// Called if a panic ( error ) occurs.
@@ -849,6 +1271,48 @@ impl Transaction for DummyTransaction {
     }
 }
 
+/// Rows and column metadata of a SELECT ( see [Database::query] ).
+#[non_exhaustive]
+pub struct ResultSet {
+    /// Column names.
+    pub colnames: Vec<String>,
+    /// Column data kinds.
+    pub kinds: Vec<DataKind>,
+    /// Rows.
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// [Transaction] that collects SELECT results ( used by [Database::query] ).
+#[derive(Default)]
+struct QueryTransaction {
+    results: Vec<ResultSet>,
+    err: String,
+}
+
+impl Transaction for QueryTransaction {
+    fn begin_select(&mut self, colnames: &[String], kinds: &[DataKind]) {
+        self.results.push(ResultSet {
+            colnames: colnames.to_vec(),
+            kinds: kinds.to_vec(),
+            rows: Vec::new(),
+        });
+    }
+
+    fn selected(&mut self, values: &[Value]) {
+        if let Some(rs) = self.results.last_mut() {
+            rs.rows.push(values.to_vec());
+        }
+    }
+
+    fn set_error(&mut self, err: String) {
+        self.err = err;
+    }
+
+    fn get_error(&mut self) -> String {
+        self.err.clone()
+    }
+}
+
 /// Memory limits.
 #[non_exhaustive]
 pub struct Limits {
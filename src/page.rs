@@ -37,6 +37,7 @@ const MAX_NODE: usize = bitmask!(0, NODE_ID_BITS);
 
 /// A page in a SortedFile.
 /// Note that left subtree has nodes that compare greater.
+#[derive(Clone)]
 pub struct Page {
     /// Data storage.
     pub data: MData,
@@ -150,6 +151,16 @@ impl Page {
 
     /// Find child page number.
     pub fn find_child(&self, db: &DB, r: &dyn Record) -> u64 {
+        let rx = self.find_node(db, r);
+        if rx == 0 {
+            self.first_page
+        } else {
+            self.child_page(rx)
+        }
+    }
+
+    /// Find node for child page, result is zero if the child is first_page.
+    pub fn find_node(&self, db: &DB, r: &dyn Record) -> usize {
         let mut x = self.root;
         let mut rx = 0;
         while x != 0 {
@@ -166,11 +177,31 @@ impl Page {
                 }
             }
         }
-        if rx == 0 {
-            self.first_page
-        } else {
-            self.child_page(rx)
+        rx
+    }
+
+    /// Is r greater than every record in the page?
+    pub fn is_greatest(&self, db: &DB, r: &dyn Record) -> bool {
+        let mut x = self.root;
+        while x != 0 && self.left(x) != 0 {
+            x = self.left(x);
         }
+        x == 0 || self.compare(db, r, x) == Ordering::Greater
+    }
+
+    /// Is r less than every record in the page?
+    pub fn is_least(&self, db: &DB, r: &dyn Record) -> bool {
+        let x = self.least();
+        x == 0 || self.compare(db, r, x) == Ordering::Less
+    }
+
+    /// Node with the least record ( zero if the page has no nodes ).
+    pub fn least(&self) -> usize {
+        let mut x = self.root;
+        while x != 0 && self.right(x) != 0 {
+            x = self.right(x);
+        }
+        x
     }
 
     /// Returns node id of Record equal to r, or zero if no such node exists.
@@ -251,6 +282,74 @@ impl Page {
         }
     }
 
+    /// Is the page less than a quarter full ( a candidate for merging )?
+    pub fn sparse(&self, page_limit: usize) -> bool {
+        NODE_BASE + self.count * self.node_size < page_limit / 4
+    }
+
+    /// Would the records of this page and other fit comfortably in a single page?
+    pub fn merge_fits(&self, other: &Page, page_limit: usize) -> bool {
+        let n = self.count + other.count;
+        n < MAX_NODE && NODE_BASE + n * self.node_size <= page_limit * 3 / 4
+    }
+
+    /// Get the pair of child nodes to merge when child x is sparse, zero means first_page.
+    /// The first result is the node to be removed, the second the next greater child.
+    pub fn merge_pair(&self, x: usize) -> (usize, usize) {
+        let mut list = Vec::new();
+        self.nodes(self.root, &mut list);
+        if x == 0 {
+            match list.first() {
+                Some(a) => (*a, 0),
+                None => (0, 0),
+            }
+        } else {
+            let i = list.iter().position(|y| *y == x).unwrap();
+            (x, if i == 0 { 0 } else { list[i - 1] })
+        }
+    }
+
+    /// New page with the records of this page followed by the ( smaller ) records of other.
+    pub fn merged(&self, other: &Page) -> Page {
+        let mut result = self.new_page(self.size() + other.size());
+        result.copy_nodes(self, self.root, 0);
+        result.copy_nodes(other, other.root, 0);
+        result
+    }
+
+    /// New page with the same children, except node x is removed.
+    pub fn without(&self, x: usize) -> Page {
+        let mut result = self.new_page(self.size());
+        result.first_page = self.first_page;
+        result.copy_nodes(self, self.root, x);
+        result
+    }
+
+    /// Append the nodes of tree x ( in descending order ), except node skip.
+    fn copy_nodes(&mut self, from: &Page, x: usize, skip: usize) {
+        if x != 0 {
+            self.copy_nodes(from, from.left(x), skip);
+            if x != skip {
+                let target = self.alloc_node();
+                let dest_off = self.rec_offset(target);
+                let src_off = from.rec_offset(x);
+                let n = self.node_size - NODE_OVERHEAD;
+                self.data[dest_off..dest_off + n].copy_from_slice(&from.data[src_off..src_off + n]);
+                self.do_insert(target, None);
+            }
+            self.copy_nodes(from, from.right(x), skip);
+        }
+    }
+
+    /// Collect the nodes of tree x in descending order.
+    fn nodes(&self, x: usize, list: &mut Vec<usize>) {
+        if x != 0 {
+            self.nodes(self.left(x), list);
+            list.push(x);
+            self.nodes(self.right(x), list);
+        }
+    }
+
     /// Append a copied parent key.
     pub fn append_page_copy(&mut self, b: &[u8], cp: u64) {
         let target = self.alloc_node();
@@ -1,11 +1,15 @@
 use crate::{
-    c_bool, compile, data_kind, panic, util, AlterCol, AssignOp, Block, ColInfo, DataType, EvalEnv,
-    Expr, ExprIs, FromExpression, IndexInfo, Instruction, ObjRef, Rc, SqlError, TableExpression,
-    Token, Transaction, Value, BINARY, BOOL, DB, DO, DOUBLE, FLOAT, INT, NONE, STRING,
+    c_bool, cexp, compile, data_kind, explain, panic, profile, table, trigger, util, AlterCol,
+    AssignOp, Block, CFromExpression, CTableExpression, ColInfo, DataKind, DataType, EvalEnv, Expr,
+    ExprIs, FilterCond, FromExpression, IndexExp, IndexInfo, Instruction, ObjRef, Rc, RefCell,
+    SqlError, Table, TableExpression, Token, Transaction, TryInfo, Value, BINARY, BOOL, CASEFOLD,
+    COLLATION, COMPRESSED, DB, DO, DOUBLE, EXP_COL, FLOAT, INT, NOCASE, NONE, STRING,
 };
 use compile::{c_delete, c_for, c_function, c_select, c_set, c_table, c_te, c_update, push};
 use std::{mem, str};
-use Instruction::{Call, Execute, Jump, JumpIfFalse, PopToLocal, Return, Select, Throw};
+use Instruction::{
+    Call, Execute, Jump, JumpIfFalse, PopToLocal, PushConst, Return, Select, Throw, Try,
+};
 
 /// SQL parser.
 ///
@@ -31,6 +35,10 @@ pub struct Parser<'a> {
     token_start: usize,
     /// Source index of start of current token (including spacce).
     token_space_start: usize,
+    /// Source line of start of current token.
+    token_line: usize,
+    /// Source line and column of end of section compiled by compile_section.
+    pub section_end: (usize, usize),
     /// source slice for current token ( but string literals are in ts )
     cs: &'a [u8],
     /// String literal.
@@ -54,6 +62,8 @@ impl<'a> Parser<'a> {
             cc: 0,
             token_start: 0,
             token_space_start: 0,
+            token_line: 1,
+            section_end: (0, 0),
             token: Token::EndOfFile,
             cs: source,
             ts: String::new(),
@@ -71,6 +81,9 @@ impl<'a> Parser<'a> {
 
     /// Parse a single statement.
     fn statement(&mut self) {
+        if !self.b.parse_only {
+            self.b.lines.push((self.b.ilist.len(), self.token_line));
+        }
         if self.token == Token::Id {
             let id = self.cs;
             self.read_token();
@@ -79,6 +92,10 @@ impl<'a> Parser<'a> {
             } else {
                 match id {
                     b"ALTER" => self.s_alter(),
+                    b"ANALYZE" => {
+                        let tr = self.obj_ref();
+                        self.b.dop(DO::Analyze(tr));
+                    }
                     b"BEGIN" => self.s_begin(),
                     b"BREAK" => self.s_break(),
                     b"CREATE" => self.s_create(),
@@ -87,12 +104,19 @@ impl<'a> Parser<'a> {
                     b"DELETE" => self.s_delete(),
                     b"EXEC" => self.s_exec(),
                     b"CHECK" => self.s_check(),
+                    b"COMMIT" => self.b.dop(DO::Commit),
                     b"EXECUTE" => self.s_execute(),
+                    b"EXPLAIN" => self.s_explain(),
                     b"FOR" => self.s_for(),
                     b"GOTO" => self.s_goto(),
                     b"IF" => self.s_if(),
                     b"INSERT" => self.s_insert(),
                     b"RETURN" => self.s_return(),
+                    b"ROLLBACK" => self.s_rollback(),
+                    b"SAVEPOINT" => {
+                        let name = self.id();
+                        self.b.dop(DO::Savepoint(name));
+                    }
                     b"SELECT" => self.s_select(),
                     b"SET" => self.s_set(),
                     b"THROW" => self.s_throw(),
@@ -108,6 +132,11 @@ impl<'a> Parser<'a> {
 
     /// Parse and execute a batch of statements.
     pub fn batch(&mut self, rs: &mut dyn Transaction) {
+        self.batch_with(rs, None);
+    }
+
+    /// Parse and execute a batch of statements, args ( if any ) are assigned to the parameters of the first section.
+    pub fn batch_with(&mut self, rs: &mut dyn Transaction, mut args: Option<&[Value]>) {
         loop {
             while self.token != Token::EndOfFile && !self.test_id(b"GO") {
                 self.statement();
@@ -116,7 +145,23 @@ impl<'a> Parser<'a> {
             let mut ee = EvalEnv::new(self.b.db.clone(), rs);
             // let start = std::time::Instant::now();
             ee.alloc_locals(&self.b.local_typ, 0);
-            ee.go(&self.b.ilist);
+            if let Some(args) = args.take() {
+                let (names, locals) = self.b.params();
+                ee.bind(&names, &locals, args);
+            }
+            if ee.profile {
+                let start = profile::Start::new(&ee);
+                ee.go_profiled(
+                    &self.b.ilist,
+                    &self.b.lines,
+                    "batch",
+                    tos(self.source),
+                    &mut 0,
+                );
+                profile::record_batch(&ee.db, &start.stats(&ee));
+            } else {
+                ee.go(&self.b.ilist);
+            }
             if self.token == Token::EndOfFile {
                 break;
             }
@@ -124,6 +169,35 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Compile section n of a batch without executing it ( sections are separated by GO ).
+    /// Other sections are only parsed. Result is the number of sections.
+    pub fn compile_section(&mut self, n: usize) -> usize {
+        let mut compiled = None;
+        let mut i = 0;
+        loop {
+            self.b.parse_only = i != n;
+            while self.token != Token::EndOfFile && !self.test_id(b"GO") {
+                self.statement();
+            }
+            if i == n {
+                self.b.resolve_jumps();
+                self.section_end = (self.prev_source_line, self.prev_source_column);
+            }
+            let db = self.b.db.clone();
+            let b = mem::replace(&mut self.b, Block::new(db));
+            if i == n {
+                compiled = Some(b);
+            }
+            i += 1;
+            if self.token == Token::EndOfFile {
+                if let Some(b) = compiled {
+                    self.b = b;
+                }
+                return i;
+            }
+        }
+    }
+
     /// Parse the definition of a function.
     pub fn parse_function(&mut self) {
         self.read(Token::LBra);
@@ -159,6 +233,33 @@ impl<'a> Parser<'a> {
         self.b.resolve_jumps();
     }
 
+    /// Parse the body of a trigger. The parameters ( names and types ) are the old and new row values.
+    pub fn parse_trigger(&mut self, names: &'a [String], typ: &[DataType]) {
+        for (name, typ) in names.iter().zip(typ) {
+            self.b.def_local(name.as_bytes(), *typ);
+            self.b.param_count += 1;
+        }
+        self.b.return_type = NONE;
+        self.read_id(b"AS");
+        self.read_id(b"BEGIN");
+        self.s_begin();
+        self.b.resolve_jumps();
+    }
+
+    /// Parse the WHERE condition of a partial index.
+    pub fn parse_index_filter(&mut self, table: &Rc<Table>) -> Vec<FilterCond> {
+        let mut e = self.exp();
+        self.b.from = Some(CTableExpression::Base(table.clone()));
+        table::filter_conds(&self.b, &mut e)
+    }
+
+    /// Parse a key expression of an index.
+    pub fn parse_index_exp(&mut self, table: &Rc<Table>) -> IndexExp {
+        let mut e = self.exp();
+        self.b.from = Some(CTableExpression::Base(table.clone()));
+        table::index_exp(&self.b, &mut e)
+    }
+
     /// Read a byte, adjusting source line/column.
     fn read_char(&mut self) -> u8 {
         let cc;
@@ -193,12 +294,13 @@ impl<'a> Parser<'a> {
                 cc = self.read_char();
             }
             self.token_start = self.source_ix - 1;
+            self.token_line = self.source_line;
             let sc: u8 = cc;
             cc = self.read_char();
             match sc {
                 b'A'..=b'Z' | b'a'..=b'z' | b'@' => {
                     token = Token::Id;
-                    while cc.is_ascii_alphabetic() {
+                    while cc.is_ascii_alphanumeric() || cc == b'_' {
                         cc = self.read_char();
                     }
                     self.cs = &self.source[self.token_start..self.source_ix - 1];
@@ -219,6 +321,16 @@ impl<'a> Parser<'a> {
                         let part1 = self.source_ix - 1;
                         let s = str::from_utf8(&self.source[self.token_start..part1]).unwrap();
                         self.decimal_int = s.parse().unwrap();
+                        if cc == b'.'
+                            && self.source_ix < self.source.len()
+                            && self.source[self.source_ix].is_ascii_digit()
+                        {
+                            token = Token::Decimal;
+                            cc = self.read_char();
+                            while cc.is_ascii_digit() {
+                                cc = self.read_char();
+                            }
+                        }
                     }
                     self.cs = &self.source[self.token_start..self.source_ix - 1];
                 }
@@ -383,6 +495,35 @@ impl<'a> Parser<'a> {
         t
     }
 
+    /// Read a column data type, which may have attributes.
+    fn read_column_type(&mut self) -> DataType {
+        let mut t = self.read_data_type();
+        loop {
+            if self.test_id(b"COMPRESSED") {
+                assert!(
+                    data_kind(t) == DataKind::Binary,
+                    "COMPRESSED only allowed for binary columns"
+                );
+                t |= COMPRESSED;
+            } else if self.test_id(b"COLLATE") {
+                assert!(
+                    data_kind(t) == DataKind::String,
+                    "COLLATE only allowed for string columns"
+                );
+                t &= !COLLATION;
+                if self.test_id(b"NOCASE") {
+                    t |= NOCASE;
+                } else if self.test_id(b"CASEFOLD") {
+                    t |= CASEFOLD;
+                } else if !self.test_id(b"BINARY") {
+                    panic!("unknown collation, expected NOCASE, CASEFOLD or BINARY");
+                }
+            } else {
+                return t;
+            }
+        }
+    }
+
     /// Examine current token, determine if it is an operator.
     /// Result is operator token and precedence, or -1 if current token is not an operator.
     fn operator(&mut self) -> (Token, i8) {
@@ -462,6 +603,39 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Tests whether the token after the current token is the specified id. Nothing is consumed.
+    fn next_is_id(&mut self, s: &[u8]) -> bool {
+        let save = (
+            self.source_ix,
+            self.cc,
+            self.token,
+            self.token_start,
+            self.token_space_start,
+            self.token_line,
+            self.cs,
+            mem::take(&mut self.ts),
+            (self.source_column, self.source_line),
+            (self.prev_source_column, self.prev_source_line),
+            self.decimal_int,
+        );
+        self.read_token();
+        let result = self.token == Token::Id && self.cs == s;
+        (
+            self.source_ix,
+            self.cc,
+            self.token,
+            self.token_start,
+            self.token_space_start,
+            self.token_line,
+            self.cs,
+            self.ts,
+            (self.source_column, self.source_line),
+            (self.prev_source_column, self.prev_source_line),
+            self.decimal_int,
+        ) = save;
+        result
+    }
+
     /// Reads an ObjRef ( schema.name pair ).
     fn obj_ref(&mut self) -> ObjRef {
         let schema = self.id();
@@ -578,6 +752,10 @@ impl<'a> Parser<'a> {
             let value = self.decimal_int;
             result = Expr::new(ExprIs::Const(Value::Int(value)));
             self.read_token();
+        } else if self.token == Token::Decimal {
+            let value = str::from_utf8(self.cs).unwrap().parse().unwrap();
+            result = Expr::new(ExprIs::Const(Value::Float(value)));
+            self.read_token();
         } else if self.token == Token::Hex {
             assert!(
                 self.cs.len() % 2 == 0,
@@ -771,8 +949,15 @@ impl<'a> Parser<'a> {
 
     fn s_select(&mut self) {
         let se = self.select_expression(false);
+        // FOR JSON following a SELECT formats the result ( so a FOR statement there cannot assign a local named JSON ).
+        let json = self.token == Token::Id && self.cs == b"FOR" && self.next_is_id(b"JSON");
+        if json {
+            self.read_token();
+            self.read_token();
+        }
         if !self.b.parse_only {
-            let cte = c_select(&mut self.b, se);
+            let mut cte = c_select(&mut self.b, se);
+            cte.json = json;
             self.b.add(Select(Box::new(cte)));
         }
     }
@@ -922,10 +1107,27 @@ impl<'a> Parser<'a> {
         let mut ti = ColInfo::empty(name);
         while !self.test(Token::RBra) {
             let cname = self.id();
-            let typ = self.read_data_type();
+            let typ = self.read_column_type();
             assert!(!ti.add(cname, typ), "duplicate column name");
             self.test(Token::Comma);
         }
+        // Rows are stored in PRIMARY KEY order, so a lookup by Id ( WHERE Id = x ) scans the table.
+        if self.test_id(b"PRIMARY") {
+            self.read_id(b"KEY");
+            let mut key = Vec::new();
+            for cname in self.index_col_names() {
+                if let Some(cnum) = ti.colmap.get(&cname) {
+                    if ti.typ[*cnum] & COMPRESSED != 0 {
+                        panic!("cannot use COMPRESSED column {} in PRIMARY KEY", cname);
+                    }
+                    assert!(!key.contains(cnum), "duplicate PRIMARY KEY column");
+                    key.push(*cnum);
+                } else {
+                    panic!("PRIMARY KEY column name not found {}", cname);
+                }
+            }
+            ti.set_key(key);
+        }
         if !self.b.parse_only {
             let _source = self.source_from(source_start, self.token_start);
             self.b.dop(DO::CreateTable(ti));
@@ -936,10 +1138,20 @@ impl<'a> Parser<'a> {
         let iname = self.id();
         self.read_id(b"ON");
         let tname = self.obj_ref();
+        // Key columns may be expressions.
         self.read(Token::LBra);
-        let mut cnames = Vec::new();
+        let mut keys = Vec::new();
         loop {
-            cnames.push(self.id());
+            let start = self.token_start;
+            let e = self.exp();
+            let source = self.source_from(start, self.token_space_start);
+            let desc = if self.test_id(b"DESC") {
+                true
+            } else {
+                self.test_id(b"ASC");
+                false
+            };
+            keys.push((e, source, desc));
             if self.test(Token::RBra) {
                 break;
             }
@@ -949,19 +1161,102 @@ impl<'a> Parser<'a> {
             );
             self.read_token();
         }
+        let inames = if self.test_id(b"INCLUDE") {
+            self.index_col_names()
+        } else {
+            Vec::new()
+        };
+        let filter = if self.test_id(b"WHERE") {
+            let start = self.token_start;
+            let e = self.exp();
+            Some((e, self.source_from(start, self.token_space_start)))
+        } else {
+            None
+        };
+        let online = self.test_id(b"ONLINE");
         if !self.b.parse_only {
-            let mut cols = Vec::new();
             let table = c_table(&self.b, &tname);
-            for cname in &cnames {
-                if let Some(cnum) = table.info.colmap.get(cname) {
-                    cols.push(*cnum);
+            let mut cols = Vec::new();
+            let mut exps = Vec::new();
+            let mut desc = Vec::new();
+            for (mut e, source, d) in keys {
+                desc.push(d);
+                if let ExprIs::ColName(name) = &e.exp {
+                    cols.extend(Self::index_cols(
+                        &table,
+                        std::slice::from_ref(name),
+                        "index",
+                    ));
                 } else {
-                    panic!("index column name not found {}", cname);
+                    // Check the expression.
+                    let save = self.b.from.replace(CTableExpression::Base(table.clone()));
+                    table::index_exp(&self.b, &mut e);
+                    self.b.from = save;
+                    cols.push(EXP_COL + exps.len());
+                    exps.push(source);
                 }
             }
-            self.b
-                .dop(DO::CreateIndex(IndexInfo { tname, iname, cols }));
+            let include = Self::index_cols(&table, &inames, "INCLUDE");
+            for c in &include {
+                if cols.contains(c) {
+                    panic!(
+                        "INCLUDE column {} is an index column",
+                        table.info.colnames[*c]
+                    );
+                }
+            }
+            // Check the WHERE condition.
+            let filter = filter.map(|(mut e, source)| {
+                let save = self.b.from.replace(CTableExpression::Base(table.clone()));
+                table::filter_conds(&self.b, &mut e);
+                self.b.from = save;
+                source
+            });
+            self.b.dop(DO::CreateIndex(IndexInfo {
+                tname,
+                iname,
+                cols,
+                include,
+                filter,
+                exps,
+                desc,
+                online,
+            }));
+        }
+    }
+
+    /// Parse bracketed list of column names.
+    fn index_col_names(&mut self) -> Vec<String> {
+        self.read(Token::LBra);
+        let mut cnames = Vec::new();
+        loop {
+            cnames.push(self.id());
+            if self.test(Token::RBra) {
+                break;
+            }
+            assert!(
+                self.token == Token::Comma,
+                "comma or closing bracket expected"
+            );
+            self.read_token();
         }
+        cnames
+    }
+
+    /// Get column numbers for index column names.
+    fn index_cols(table: &Table, cnames: &[String], what: &str) -> Vec<usize> {
+        let mut cols = Vec::new();
+        for cname in cnames {
+            if let Some(cnum) = table.info.colmap.get(cname) {
+                if table.info.typ[*cnum] & COMPRESSED != 0 {
+                    panic!("cannot {} COMPRESSED column {}", what, cname);
+                }
+                cols.push(*cnum);
+            } else {
+                panic!("{} column name not found {}", what, cname);
+            }
+        }
+        cols
     }
 
     fn create_function(&mut self, alter: bool) {
@@ -980,9 +1275,53 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn create_trigger(&mut self) {
+        let name = self.id();
+        self.read_id(b"ON");
+        let tname = self.obj_ref();
+        self.read_id(b"AFTER");
+        let mut events = 0;
+        loop {
+            events |= match self.id_ref() {
+                b"INSERT" => trigger::INSERT,
+                b"UPDATE" => trigger::UPDATE,
+                b"DELETE" => trigger::DELETE,
+                _ => panic!("INSERT, UPDATE or DELETE expected"),
+            };
+            if !self.test(Token::Comma) {
+                break;
+            }
+        }
+        // Parse the body ( the parameters are the old and new row values ) with a separate parser.
+        let source_start = self.token_start;
+        let (names, typ) = if let Some(t) = self.b.db.get_table(&tname) {
+            trigger::params(&t.info)
+        } else if self.b.parse_only {
+            (Vec::new(), Vec::new())
+        } else {
+            panic!("table {} not found", tname.str())
+        };
+        let end = {
+            let mut p = Parser::new(tos(&self.source[source_start..]), &self.b.db);
+            p.b.parse_only = true;
+            p.parse_trigger(&names, &typ);
+            source_start + p.token_space_start
+        };
+        while self.token != Token::EndOfFile && self.token_start < end {
+            self.read_token();
+        }
+        if self.b.parse_only {
+            return;
+        }
+        let source = self.source_from(source_start, end);
+        self.b
+            .dop(DO::CreateTrigger(name, tname, events, Rc::new(source)));
+    }
+
     fn s_create(&mut self) {
         match self.id_ref() {
             b"FN" => self.create_function(false),
+            b"TRIGGER" => self.create_trigger(),
             b"TABLE" => self.create_table(),
             b"SCHEMA" => {
                 let name = self.id();
@@ -1017,6 +1356,12 @@ impl<'a> Parser<'a> {
                 let fr = self.obj_ref();
                 self.b.dop(DO::DropFunction(fr));
             }
+            b"TRIGGER" => {
+                let name = self.id();
+                self.read_id(b"ON");
+                let tr = self.obj_ref();
+                self.b.dop(DO::DropTrigger(tr, name));
+            }
             b"SCHEMA" => {
                 let s = self.id();
                 self.b.dop(DO::DropSchema(s));
@@ -1033,14 +1378,14 @@ impl<'a> Parser<'a> {
         loop {
             if self.test_id(b"ADD") {
                 let col = self.id();
-                let datatype = self.read_data_type();
+                let datatype = self.read_column_type();
                 list.push(AlterCol::Add(col, datatype));
             } else if self.test_id(b"DROP") {
                 let col = self.id();
                 list.push(AlterCol::Drop(col));
             } else if self.test_id(b"MODIFY") {
                 let col = self.id();
-                let datatype = self.read_data_type();
+                let datatype = self.read_column_type();
                 list.push(AlterCol::Modify(col, datatype));
             } else {
                 break;
@@ -1126,16 +1471,107 @@ impl<'a> Parser<'a> {
 
     fn s_throw(&mut self) {
         let mut msg = self.exp();
+        let mut number = None;
+        if self.test(Token::Comma) {
+            number = Some(msg);
+            msg = self.exp();
+        }
         if !self.b.parse_only {
+            if let Some(mut number) = number {
+                if self.b.kind(&mut number) != DataKind::Int {
+                    panic!("THROW error number must be int");
+                }
+                push(&mut self.b, &mut number);
+            } else {
+                self.b.add(PushConst(Value::Int(0)));
+            }
             push(&mut self.b, &mut msg);
             self.b.add(Throw);
         }
     }
 
     fn s_begin(&mut self) {
+        if self.test_id(b"TRY") {
+            self.s_try();
+            return;
+        }
+        if self.test_id(b"TRANSACTION") {
+            self.b.dop(DO::Begin);
+            return;
+        }
+        while !self.test_id(b"END") {
+            self.statement();
+        }
+    }
+
+    fn s_explain(&mut self) {
+        let start = self.b.ilist.len();
+        let save = self.b.explain.replace(RefCell::new(Vec::new()));
+        self.statement();
+        let mut lines = mem::replace(&mut self.b.explain, save)
+            .unwrap()
+            .into_inner();
+        if self.b.parse_only {
+            return;
+        }
+        let ilist: Vec<Instruction> = self.b.ilist.drain(start..).collect();
+        for (i, inst) in ilist.iter().enumerate() {
+            lines.push(format!(
+                "{} {}",
+                i,
+                explain::inst_text(&self.b, inst, start)
+            ));
+        }
+        let plan = Value::String(Rc::new(lines.join("\n")));
+        let cse = CFromExpression {
+            colnames: vec!["Plan".to_string()],
+            kinds: vec![DataKind::String],
+            assigns: Vec::new(),
+            exps: vec![Box::new(cexp::Const(plan))],
+            from: None,
+            wher: None,
+            orderby: Vec::new(),
+            desc: Vec::new(),
+            json: false,
+        };
+        self.b.add(Select(Box::new(cse)));
+    }
+
+    fn s_rollback(&mut self) {
+        if self.test_id(b"TO") {
+            let name = self.id();
+            self.b.dop(DO::RollbackTo(name));
+        } else {
+            self.b.dop(DO::Rollback);
+        }
+    }
+
+    fn s_try(&mut self) {
+        let catch = self.b.get_jump_id();
+        let end = self.b.get_jump_id();
+        let ix = self.b.ilist.len();
+        self.b.add(Try(Box::new(TryInfo {
+            catch,
+            end,
+            lines: Vec::new(),
+        })));
+        let mut lines = Vec::new();
         while !self.test_id(b"END") {
+            lines.push((self.b.ilist.len(), self.token_line));
             self.statement();
         }
+        self.read_id(b"TRY");
+        self.read_id(b"BEGIN");
+        self.read_id(b"CATCH");
+        self.b.set_jump(catch);
+        while !self.test_id(b"END") {
+            self.statement();
+        }
+        self.read_id(b"CATCH");
+        self.b.set_jump(end);
+        if let Some(Try(info)) = self.b.ilist.get_mut(ix) {
+            info.lines = lines;
+        }
     }
 } // end impl Parser
 
@@ -0,0 +1,195 @@
+use crate::*;
+use std::time::{Duration, Instant};
+
+/// Execution statistics.
+#[derive(Default, Clone, Debug)]
+#[non_exhaustive]
+pub struct Stats {
+    /// Number of executions.
+    pub count: u64,
+    /// Total time.
+    pub time: Duration,
+    /// Rows read from tables.
+    pub rows_scanned: u64,
+    /// Rows read from tables that satisfied the WHERE condition.
+    pub rows_returned: u64,
+    /// Page accesses satisfied from the stash ( pages already loaded ).
+    pub pages_read: u64,
+    /// Pages loaded from storage.
+    pub pages_loaded: u64,
+}
+
+impl Stats {
+    /// Add statistics.
+    pub fn add(&mut self, x: &Stats) {
+        self.count += x.count;
+        self.time += x.time;
+        self.rows_scanned += x.rows_scanned;
+        self.rows_returned += x.rows_returned;
+        self.pages_read += x.pages_read;
+        self.pages_loaded += x.pages_loaded;
+    }
+}
+
+/// Statement execution that took at least the [Profiler] threshold.
+#[non_exhaustive]
+pub struct SlowStatement<'a> {
+    /// Routine name ( "batch" or function name ).
+    pub rname: &'a str,
+    /// Source line number.
+    pub line: usize,
+    /// Source line text.
+    pub text: &'a str,
+    /// Statistics for the execution.
+    pub stats: &'a Stats,
+}
+
+/// Sink for slow statements.
+pub trait SlowLog {
+    /// Called when a statement execution takes at least the profiler threshold.
+    fn slow(&mut self, s: &SlowStatement);
+}
+
+impl<F: FnMut(&SlowStatement)> SlowLog for F {
+    fn slow(&mut self, s: &SlowStatement) {
+        self(s)
+    }
+}
+
+/// Profiler settings and collected statistics ( see [Database::set_profiler] ).
+#[non_exhaustive]
+pub struct Profiler {
+    /// Statement executions taking at least this long are reported to log.
+    pub threshold: Duration,
+    /// Sink for slow statements.
+    pub log: Option<Box<dyn SlowLog>>,
+    /// Statistics for each statement, key is routine name ( "batch" or function name ) and source line.
+    pub statements: BTreeMap<(String, usize), Stats>,
+    /// Statistics for each called function ( including time spent in functions it calls ).
+    pub functions: BTreeMap<String, Stats>,
+    /// Statistics for batches ( each section of a batch separated by GO is counted as one execution ).
+    pub batches: Stats,
+}
+
+impl Profiler {
+    /// Construct a new Profiler.
+    pub fn new(threshold: Duration, log: Option<Box<dyn SlowLog>>) -> Self {
+        Self {
+            threshold,
+            log,
+            statements: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            batches: Stats::default(),
+        }
+    }
+}
+
+/// Counters used to compute statistics for an execution.
+pub(crate) struct Start {
+    time: Instant,
+    rows_scanned: u64,
+    rows_returned: u64,
+    read: u64,
+    miss: u64,
+}
+
+impl Start {
+    /// Record the counters at the start of an execution.
+    pub fn new(ee: &EvalEnv) -> Self {
+        let (read, miss) = {
+            let s = ee.db.apd.stash();
+            (s.read, s.miss)
+        };
+        Self {
+            time: Instant::now(),
+            rows_scanned: ee.rows_scanned,
+            rows_returned: ee.rows_returned,
+            read,
+            miss,
+        }
+    }
+
+    /// Get the statistics for the execution.
+    pub fn stats(&self, ee: &EvalEnv) -> Stats {
+        let (read, miss) = {
+            let s = ee.db.apd.stash();
+            (s.read, s.miss)
+        };
+        let loaded = miss - self.miss;
+        Stats {
+            count: 1,
+            time: self.time.elapsed(),
+            rows_scanned: ee.rows_scanned - self.rows_scanned,
+            rows_returned: ee.rows_returned - self.rows_returned,
+            pages_read: read - self.read - loaded,
+            pages_loaded: loaded,
+        }
+    }
+}
+
+impl EvalEnv<'_> {
+    /// Execute list of instructions, recording statistics for each statement.
+    /// lines has the instruction index and source line of each statement, ip is updated as instructions are executed.
+    pub(crate) fn go_profiled(
+        &mut self,
+        ilist: &[Instruction],
+        lines: &[(usize, usize)],
+        rname: &str,
+        source: &str,
+        ip: &mut usize,
+    ) {
+        let line_of = |ip: usize| match lines.binary_search_by(|x| x.0.cmp(&ip)) {
+            Ok(i) => lines[i].1,
+            Err(0) => 0,
+            Err(i) => lines[i - 1].1,
+        };
+        while *ip < ilist.len() {
+            let line = line_of(*ip);
+            let start = Start::new(self);
+            // Execute instructions until the statement changes.
+            loop {
+                *ip = self.exec(ilist, *ip, *ip, *ip + 1);
+                if *ip >= ilist.len() || line_of(*ip) != line {
+                    break;
+                }
+            }
+            let stats = start.stats(self);
+            record(&self.db, rname, line, source, &stats);
+        }
+    }
+}
+
+/// Record statistics for a statement execution, reporting it to the log if it is slow.
+fn record(db: &DB, rname: &str, line: usize, source: &str, stats: &Stats) {
+    if let Some(p) = &mut *db.profiler.borrow_mut() {
+        p.statements
+            .entry((rname.to_string(), line))
+            .or_default()
+            .add(stats);
+        if stats.time >= p.threshold {
+            if let Some(log) = &mut p.log {
+                let text = source.lines().nth(line.wrapping_sub(1)).unwrap_or("");
+                log.slow(&SlowStatement {
+                    rname,
+                    line,
+                    text: text.trim(),
+                    stats,
+                });
+            }
+        }
+    }
+}
+
+/// Record statistics for a function call.
+pub(crate) fn record_function(db: &DB, name: &str, stats: &Stats) {
+    if let Some(p) = &mut *db.profiler.borrow_mut() {
+        p.functions.entry(name.to_string()).or_default().add(stats);
+    }
+}
+
+/// Record statistics for a batch section.
+pub(crate) fn record_batch(db: &DB, stats: &Stats) {
+    if let Some(p) = &mut *db.profiler.borrow_mut() {
+        p.batches.add(stats);
+    }
+}
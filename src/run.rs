@@ -1,7 +1,8 @@
 use crate::{
-    panic, Assigns, Block, Cell, ColInfo, DataType, EvalEnv, Expr, IndexInfo, ObjRef, PagePtr, Rc,
-    RefCell, Table, Value,
+    panic, Arc, Assigns, Block, Cell, ColInfo, DataKind, DataType, EvalEnv, Expr, IndexInfo,
+    ObjRef, PagePtr, Rc, RefCell, Table, Value,
 };
+use std::{sync::atomic::AtomicBool, time::Duration};
 
 /// Instruction.
 #[non_exhaustive]
@@ -24,6 +25,8 @@ pub enum Instruction {
     Return,
     /// Throw error.
     Throw,
+    /// BEGIN TRY .. BEGIN CATCH.
+    Try(Box<TryInfo>),
     /// Execute string.
     Execute,
     /// Initialise FOR statement.
@@ -58,6 +61,82 @@ pub enum Instruction {
     DecLocal(usize, CExpPtr<Value>),
 }
 
+/// Information for BEGIN TRY .. BEGIN CATCH.
+#[non_exhaustive]
+pub struct TryInfo {
+    /// Start of CATCH block ( end of TRY block ).
+    pub catch: usize,
+    /// End of CATCH block.
+    pub end: usize,
+    /// Instruction index and source line of each statement in the TRY block.
+    pub lines: Vec<(usize, usize)>,
+}
+
+/// Error caught by BEGIN CATCH.
+#[non_exhaustive]
+pub struct CaughtError {
+    /// Error message.
+    pub msg: String,
+    /// Source line of statement in TRY block that raised the error.
+    pub line: usize,
+    /// Error number ( from THROW, zero otherwise ).
+    pub number: i64,
+}
+
+/// Batch of SQL statements compiled by [crate::Database::prepare].
+#[non_exhaustive]
+pub struct Prepared {
+    /// Source SQL.
+    pub source: String,
+    /// Parameters ( local variables of the first section with names starting with '@' ) : name and type.
+    pub params: Vec<(String, DataType)>,
+    /// Compiled sections ( sections are separated by GO ), None if not yet compiled.
+    pub(crate) sections: RefCell<Vec<Option<Rc<Section>>>>,
+    /// Schema generation when sections were compiled.
+    pub(crate) generation: Cell<u64>,
+    /// Database the sections were compiled for.
+    pub(crate) db: RefCell<std::rc::Weak<crate::Database>>,
+}
+
+/// Limits on the execution of a batch ( see [crate::Database::set_limits] ).
+/// When a limit is reached an error is raised ( it cannot be caught by BEGIN CATCH ) and the batch is rolled back.
+#[derive(Clone, Default)]
+#[non_exhaustive]
+pub struct RunLimits {
+    /// Maximum number of instructions executed.
+    pub instructions: Option<u64>,
+    /// Maximum number of rows read from tables.
+    pub rows: Option<u64>,
+    /// Maximum elapsed time.
+    pub time: Option<Duration>,
+    /// Cancellation token, the batch is cancelled when it is set to true ( the token is not reset ).
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl RunLimits {
+    /// Are there no limits?
+    pub fn is_unlimited(&self) -> bool {
+        self.instructions.is_none()
+            && self.rows.is_none()
+            && self.time.is_none()
+            && self.cancel.is_none()
+    }
+}
+
+/// Compiled section of a prepared batch.
+pub(crate) struct Section {
+    /// List of instructions.
+    pub ilist: Vec<Instruction>,
+    /// Types of local variables.
+    pub local_typ: Vec<DataType>,
+    /// Local variable numbers of parameters.
+    pub params: Vec<usize>,
+    /// Instruction index and source line of each statement.
+    pub lines: Vec<(usize, usize)>,
+    /// Source line and column of end of section ( for error reporting ).
+    pub end: (usize, usize),
+}
+
 /// Compiled Function.
 #[non_exhaustive]
 pub struct Function {
@@ -67,10 +146,14 @@ pub struct Function {
     pub return_type: DataType,
     /// Types of local parameters/variables.
     pub local_typ: Vec<DataType>,
+    /// Function name.
+    pub name: ObjRef,
     /// Source SQL.
     pub source: Rc<String>,
     /// List of instructions.
     pub ilist: RefCell<Vec<Instruction>>, // Valid when compiled is true.
+    /// Instruction index and source line of each statement.
+    pub lines: RefCell<Vec<(usize, usize)>>,
     /// Has function been compiled.
     pub compiled: Cell<bool>,
 }
@@ -94,6 +177,8 @@ pub enum CompileFunc {
     Int(fn(&Block, &mut [Expr]) -> CExpPtr<i64>),
     /// Float result.
     Float(fn(&Block, &mut [Expr]) -> CExpPtr<f64>),
+    /// Bool result.
+    Bool(fn(&Block, &mut [Expr]) -> CExpPtr<bool>),
 }
 
 /// Iterator that yields references to page data.
@@ -147,6 +232,16 @@ pub enum CTableExpression {
     IdGet(Rc<Table>, CExpPtr<i64>),
     /// Indexed rows.
     IxGet(Rc<Table>, Vec<CExpPtr<Value>>, usize),
+    /// Indexed rows, read from a covering index ( the index records are used instead of the table records ).
+    IxCover(Rc<Table>, Vec<CExpPtr<Value>>, usize),
+    /// Rows identified by a list of Ids.
+    IdList(Rc<Table>, Vec<CExpPtr<i64>>),
+    /// Indexed rows, for a list of keys of the first index column.
+    IxList(Rc<Table>, Vec<CExpPtr<Value>>, usize),
+    /// Indexed rows ( IxGet or IxCover ), in reverse index order.
+    IxRev(Box<CTableExpression>),
+    /// Rows of a table with a PRIMARY KEY, with matching values for the leading key columns.
+    KeyGet(Rc<Table>, Vec<CExpPtr<Value>>),
     /// VALUE expressions.
     Values(Vec<Vec<CExpPtr<Value>>>),
 }
@@ -158,6 +253,11 @@ impl CTableExpression {
             CTableExpression::Base(t) => t.clone(),
             CTableExpression::IdGet(t, _) => t.clone(),
             CTableExpression::IxGet(t, _, _) => t.clone(),
+            CTableExpression::IxCover(t, _, _) => t.clone(),
+            CTableExpression::IdList(t, _) => t.clone(),
+            CTableExpression::IxList(t, _, _) => t.clone(),
+            CTableExpression::IxRev(x) => x.table(),
+            CTableExpression::KeyGet(t, _) => t.clone(),
             _ => panic!(),
         }
     }
@@ -168,6 +268,8 @@ impl CTableExpression {
 pub struct CFromExpression {
     /// Column names.
     pub colnames: Vec<String>,
+    /// Column data kinds.
+    pub kinds: Vec<DataKind>,
     /// Assignments ( left hand side ).
     pub assigns: Assigns,
     /// Expressions.
@@ -180,6 +282,8 @@ pub struct CFromExpression {
     pub orderby: Vec<CExpPtr<Value>>,
     /// DESC bits.
     pub desc: Vec<bool>,
+    /// Output rows as a JSON array of objects ( SELECT .. FOR JSON ).
+    pub json: bool,
 }
 
 /// Database Operation
@@ -203,6 +307,12 @@ pub enum DO {
     DropIndex(ObjRef, String),
     /// Drop Function.
     DropFunction(ObjRef),
+    /// Create Trigger : name, table, events, source.
+    CreateTrigger(String, ObjRef, u8, Rc<String>),
+    /// Drop Trigger : table, name.
+    DropTrigger(ObjRef, String),
+    /// Collect index statistics for a table.
+    Analyze(ObjRef),
     /// Insert into Table.
     Insert(Rc<Table>, Vec<usize>, CTableExpression),
     /// Update Table rows.
@@ -213,6 +323,16 @@ pub enum DO {
     ),
     /// Delete Table rows.
     Delete(CTableExpression, Option<CExpPtr<bool>>),
+    /// Begin transaction.
+    Begin,
+    /// Commit changes.
+    Commit,
+    /// Rollback transaction.
+    Rollback,
+    /// Record savepoint.
+    Savepoint(String),
+    /// Rollback to savepoint.
+    RollbackTo(String),
 }
 
 /// Actions for altering columns of a table.
@@ -0,0 +1,161 @@
+use crate::*;
+
+/// Unsaved database state recorded by SAVEPOINT or BEGIN TRANSACTION.
+pub struct Savepoint {
+    /// Savepoint name ( empty for BEGIN TRANSACTION ).
+    name: String,
+    /// State of the byte storage files.
+    bs: Vec<(FileState, u64)>,
+    /// State of the loaded tables.
+    tables: Vec<(ObjRef, Rc<Table>, TableState)>,
+    /// Loaded schemas.
+    schemas: HashMap<String, i64>,
+    /// Last id generated by INSERT.
+    lastid: i64,
+    /// Number of pages allocated when savepoint was recorded.
+    allocated: usize,
+    /// Number of pages freed when savepoint was recorded.
+    freed: usize,
+}
+
+/// Start a transaction ( BEGIN TRANSACTION ).
+pub fn begin(db: &DB) {
+    if find(db, "").is_some() {
+        panic!("transaction already started");
+    }
+    create(db, "");
+}
+
+/// Commit all changes ( COMMIT ).
+pub fn commit(db: &DB) {
+    db.save();
+}
+
+/// Undo changes since BEGIN TRANSACTION ( ROLLBACK ).
+pub fn rollback(db: &DB) {
+    if let Some(i) = find(db, "") {
+        restore(db, i);
+        discard(db, i);
+    } else {
+        panic!("ROLLBACK without BEGIN TRANSACTION");
+    }
+}
+
+/// Record a savepoint ( SAVEPOINT name ).
+pub fn create(db: &DB, name: &str) {
+    let tables = db
+        .tables
+        .borrow()
+        .iter()
+        .map(|(n, t)| (n.clone(), t.clone(), t.state()))
+        .collect();
+    let sp = Savepoint {
+        name: name.to_string(),
+        bs: db.bs.iter().map(|bs| bs.state()).collect(),
+        tables,
+        schemas: db.schemas.borrow().clone(),
+        lastid: db.lastid.get(),
+        allocated: db.allocated.borrow().len(),
+        freed: db.freed.borrow().len(),
+    };
+    db.savepoints.borrow_mut().push(sp);
+}
+
+/// Undo changes since savepoint ( ROLLBACK TO name ). The savepoint is retained.
+pub fn rollback_to(db: &DB, name: &str) {
+    if let Some(i) = find(db, name) {
+        restore(db, i);
+        discard(db, i + 1);
+    } else {
+        panic!("savepoint not found: {}", name);
+    }
+}
+
+/// Name of the implicit savepoint recorded for a statement run under TRY ( not a valid identifier ).
+const TRY_NAME: &str = " try";
+
+/// Record an implicit savepoint before a statement run under TRY, returns its position.
+pub fn mark(db: &DB) -> usize {
+    let i = db.savepoints.borrow().len();
+    create(db, TRY_NAME);
+    i
+}
+
+/// Discard the implicit savepoint at position i after the statement succeeded.
+pub fn release(db: &DB, i: usize) {
+    if is_mark(db, i) {
+        let sp = db.savepoints.borrow_mut().remove(i);
+        release_state(db, &sp);
+    }
+}
+
+/// Undo changes made by a statement that failed under TRY.
+pub fn undo(db: &DB, i: usize) {
+    if is_mark(db, i) {
+        restore(db, i);
+        discard(db, i);
+    }
+}
+
+/// Is the savepoint at position i an implicit TRY savepoint? ( COMMIT or ROLLBACK may have removed it. )
+fn is_mark(db: &DB, i: usize) -> bool {
+    db.savepoints
+        .borrow()
+        .get(i)
+        .is_some_and(|sp| sp.name == TRY_NAME)
+}
+
+/// Find the most recent savepoint with specified name.
+fn find(db: &DB, name: &str) -> Option<usize> {
+    db.savepoints
+        .borrow()
+        .iter()
+        .rposition(|sp| sp.name == name)
+}
+
+/// Remove savepoints from position i onwards.
+fn discard(db: &DB, i: usize) {
+    let removed: Vec<Savepoint> = db.savepoints.borrow_mut().drain(i..).collect();
+    for sp in removed.iter().rev() {
+        release_state(db, sp);
+    }
+}
+
+/// Stop recording changes for a savepoint that has been removed.
+fn release_state(db: &DB, sp: &Savepoint) {
+    for (bs, s) in db.bs.iter().zip(&sp.bs) {
+        bs.release(s);
+    }
+    for (_, t, s) in &sp.tables {
+        t.release(s);
+    }
+}
+
+/// Restore the state recorded by savepoint i.
+fn restore(db: &DB, i: usize) {
+    let sps = db.savepoints.borrow();
+    let sp = &sps[i];
+    for (bs, s) in db.bs.iter().zip(&sp.bs) {
+        bs.restore(s);
+    }
+    {
+        let tables = &mut *db.tables.borrow_mut();
+        tables.clear();
+        for (name, t, s) in &sp.tables {
+            t.restore(s);
+            tables.insert(name.clone(), t.clone());
+        }
+    }
+    *db.schemas.borrow_mut() = sp.schemas.clone();
+    db.lastid.set(sp.lastid);
+
+    // Pages freed since the savepoint are in use again, pages allocated since the savepoint are no longer used.
+    db.freed.borrow_mut().truncate(sp.freed);
+    for lpnum in db.allocated.borrow_mut().drain(sp.allocated..) {
+        db.apd.free_page(lpnum);
+    }
+
+    // Function definitions may have changed, compiled functions may refer to tables that no longer exist.
+    db.functions.borrow_mut().clear();
+    db.schema_gen.set(db.schema_gen.get() + 1);
+}
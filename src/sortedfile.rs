@@ -16,6 +16,23 @@ pub struct SortedFile {
     pub root_page: Cell<u64>,
     /// Status
     pub ok: Cell<bool>,
+    /// Undo log, one level for each savepoint ( see [SortedFile::state] ).
+    undo: RefCell<Vec<UndoLevel>>,
+    /// Id of the next undo level.
+    undo_id: Cell<u64>,
+}
+
+/// Unsaved state of a [SortedFile] ( see [SortedFile::state] ).
+pub struct FileState {
+    level: u64,
+    root_page: u64,
+    ok: bool,
+}
+
+/// Pages changed since a savepoint, with their contents when first changed ( None if the page was not dirty ).
+struct UndoLevel {
+    id: u64,
+    pages: HashMap<u64, Option<Page>>,
 }
 
 impl SortedFile {
@@ -27,6 +44,8 @@ impl SortedFile {
             key_size,
             root_page: Cell::new(root_page),
             ok: Cell::new(true),
+            undo: RefCell::new(Vec::new()),
+            undo_id: Cell::new(0),
         }
     }
 
@@ -38,6 +57,8 @@ impl SortedFile {
 
     /// Save changes to underlying storage.
     pub fn save(&self, db: &DB, op: SaveOp) {
+        // Savepoints do not survive a save or rollback.
+        self.undo.borrow_mut().clear();
         if op == SaveOp::RollBack {
             self.rollback();
             return;
@@ -57,16 +78,95 @@ impl SortedFile {
         self.dirty_pages.borrow_mut().clear();
     }
 
+    /// Start recording changes for a savepoint. Pages are copied when they are first changed ( see [SortedFile::log_page] ).
+    pub fn state(&self) -> FileState {
+        let id = self.undo_id.get();
+        self.undo_id.set(id + 1);
+        self.undo.borrow_mut().push(UndoLevel {
+            id,
+            pages: HashMap::default(),
+        });
+        FileState {
+            level: id,
+            root_page: self.root_page.get(),
+            ok: self.ok.get(),
+        }
+    }
+
+    /// Undo changes made since a savepoint. Changes continue to be recorded for the savepoint.
+    pub fn restore(&self, s: &FileState) {
+        let undo = &mut *self.undo.borrow_mut();
+        if let Some(pos) = undo.iter().position(|x| x.id == s.level) {
+            let dp = &mut *self.dirty_pages.borrow_mut();
+            // Later savepoints are undone first.
+            for level in undo.drain(pos..).rev() {
+                for (pnum, pre) in level.pages {
+                    if let Some(p) = pre {
+                        dp.insert(pnum, util::new(p));
+                    } else {
+                        dp.remove(&pnum);
+                    }
+                }
+            }
+            undo.push(UndoLevel {
+                id: s.level,
+                pages: HashMap::default(),
+            });
+        }
+        self.root_page.set(s.root_page);
+        self.ok.set(s.ok);
+    }
+
+    /// Stop recording changes for a savepoint that is no longer needed.
+    /// Earliest copies are kept for any enclosing savepoint.
+    pub fn release(&self, s: &FileState) {
+        let undo = &mut *self.undo.borrow_mut();
+        if let Some(pos) = undo.iter().position(|x| x.id == s.level) {
+            let level = undo.remove(pos);
+            if pos > 0 {
+                let prev = &mut undo[pos - 1].pages;
+                for (pnum, pre) in level.pages {
+                    prev.entry(pnum).or_insert(pre);
+                }
+            }
+        }
+    }
+
+    /// Record the contents of a page before it is first changed after a savepoint.
+    fn log_page(&self, p: &Page) {
+        if let Some(level) = self.undo.borrow_mut().last_mut() {
+            level
+                .pages
+                .entry(p.pnum)
+                .or_insert_with(|| p.is_dirty.then(|| p.clone()));
+        }
+    }
+
+    /// Record the contents of a cached page before it is first replaced after a savepoint.
+    fn log_pnum(&self, pnum: u64) {
+        if let Some(level) = self.undo.borrow_mut().last_mut() {
+            level.pages.entry(pnum).or_insert_with(|| {
+                let dp = self.dirty_pages.borrow();
+                dp.get(&pnum).map(|pp| pp.borrow().clone())
+            });
+        }
+    }
+
     /// Free the underlying storage. File is not useable after this.
     pub fn free_pages(&self, db: &DB, r: &dyn Record) {
         self.free_page(db, self.root_page.get(), r);
+        // Cached pages are discarded, a savepoint may need them.
+        let pnums: Vec<u64> = self.dirty_pages.borrow().keys().copied().collect();
+        for pnum in pnums {
+            self.log_pnum(pnum);
+        }
         self.rollback();
         self.ok.set(false);
     }
 
     /// Insert a Record. Panics if the key is a duplicate.
     pub fn insert(&self, db: &DB, r: &dyn Record) {
-        while !self.insert_leaf(db, self.root_page.get(), r, None) {
+        while !self.insert_leaf(db, self.root_page.get(), r, None, Edge::BOTH) {
             // We get here if a child page needed to be split.
         }
     }
@@ -74,18 +174,61 @@ impl SortedFile {
     /// Remove a Record.
     pub fn remove(&self, db: &DB, r: &dyn Record) {
         let mut pp = self.load_page(db, self.root_page.get());
+        let mut parent = None;
         loop {
             let cpnum = {
                 let p = &mut *pp.borrow_mut();
                 if p.level == 0 {
                     self.set_dirty(p, &pp);
                     p.remove(db, r);
+                    if !p.sparse(db.page_size_max) {
+                        return;
+                    }
                     break;
                 }
                 p.find_child(db, r)
             };
+            parent = Some(pp);
             pp = self.load_page(db, cpnum);
         }
+        if let Some(pp) = parent {
+            self.merge(db, &pp, r);
+        }
+    }
+
+    /// Merge a sparse child page ( found using r ) with an adjacent child page.
+    fn merge(&self, db: &DB, pp: &PagePtr, r: &dyn Record) {
+        let p = &*pp.borrow();
+        let (a, b) = p.merge_pair(p.find_node(db, r));
+        if a == 0 {
+            return;
+        }
+        let apnum = p.child_page(a);
+        let bpnum = if b == 0 {
+            p.first_page
+        } else {
+            p.child_page(b)
+        };
+        let (ap, bp) = (self.load_page(db, apnum), self.load_page(db, bpnum));
+        let (ap, bp) = (&*ap.borrow(), &*bp.borrow());
+        if !bp.merge_fits(ap, db.page_size_max) {
+            return;
+        }
+        let m = bp.merged(ap);
+        // The records of page a are now in m, so page a and its parent key are no longer needed.
+        self.remove_page(ap);
+        db.free_page(apnum);
+        p.drop_key(db, a, r);
+        let np = p.without(a);
+        if np.count == 0 && p.pnum == self.root_page.get() {
+            // Only one child page remains, it becomes the root page.
+            self.remove_page(bp);
+            db.free_page(bpnum);
+            self.publish_page(p.pnum, m);
+        } else {
+            self.publish_page(bpnum, m);
+            self.publish_page(p.pnum, np);
+        }
     }
 
     /// Free a page and any child pages if this is a parent page.
@@ -151,21 +294,34 @@ impl SortedFile {
     }
 
     /// Insert a record into a leaf page.
-    fn insert_leaf(&self, db: &DB, pnum: u64, r: &dyn Record, pi: Option<&ParentInfo>) -> bool {
+    fn insert_leaf(
+        &self,
+        db: &DB,
+        pnum: u64,
+        r: &dyn Record,
+        pi: Option<&ParentInfo>,
+        edge: Edge,
+    ) -> bool {
         let pp = self.load_page(db, pnum);
-        let cpnum = {
+        let (cpnum, cedge) = {
             // new block to ensure pp borrow is released before recursing.
             let p = &mut *pp.borrow_mut();
             if p.level != 0 {
-                p.find_child(db, r)
+                let x = p.find_node(db, r);
+                let cpnum = if x == 0 {
+                    p.first_page
+                } else {
+                    p.child_page(x)
+                };
+                (cpnum, edge.child(p, x))
             } else if !p.full(db.page_size_max) {
                 self.set_dirty(p, &pp);
                 p.insert(db, r);
                 return true;
             } else {
                 // Page is full, divide it into left and right.
-                self.remove_page(p.pnum);
-                let sp = Split::new(p, db);
+                self.remove_page(p);
+                let sp = Split::new(p, db, edge.mode(db, p, r));
                 let sk = &*p.get_key(db, sp.split_node, r);
                 // Could insert r into left or right here.
                 // sp.right is allocated a new page number.
@@ -187,7 +343,12 @@ impl SortedFile {
                 return false; // r has not yet been inserted.
             }
         };
-        self.insert_leaf(db, cpnum, r, Some(&ParentInfo { pnum, parent: pi }))
+        let pi = ParentInfo {
+            pnum,
+            parent: pi,
+            edge,
+        };
+        self.insert_leaf(db, cpnum, r, Some(&pi), cedge)
     }
 
     /// Insert child into a non-leaf page.
@@ -201,8 +362,8 @@ impl SortedFile {
             p.insert_page(db, r, cpnum);
         } else {
             // Split p.
-            self.remove_page(pnum);
-            let mut sp = Split::new(p, db);
+            self.remove_page(p);
+            let mut sp = Split::new(p, db, into.edge.mode(db, p, r));
             let sk = &*p.get_key(db, sp.split_node, r);
             // Insert into either left or right.
             let c = p.compare(db, r, sp.split_node);
@@ -259,6 +420,7 @@ impl SortedFile {
 
     /// Publish a page in the cache with specified page number.
     fn publish_page(&self, pnum: u64, p: Page) {
+        self.log_pnum(pnum);
         let pp = util::new(p);
         {
             let p = &mut *pp.borrow_mut();
@@ -268,8 +430,10 @@ impl SortedFile {
         self.dirty_pages.borrow_mut().insert(pnum, pp);
     }
 
-    fn remove_page(&self, pnum: u64) {
-        self.dirty_pages.borrow_mut().remove(&pnum);
+    /// Remove a page from the cache ( it is about to be replaced or freed ).
+    fn remove_page(&self, p: &Page) {
+        self.log_page(p);
+        self.dirty_pages.borrow_mut().remove(&p.pnum);
     }
 
     /// Get a page from the cache, or if it is not in the cache, load it from external storage.
@@ -294,8 +458,9 @@ impl SortedFile {
         ))
     }
 
-    /// Mark a page as changed.
+    /// Mark a page as changed. Must be called before the page is changed.
     pub fn set_dirty(&self, p: &mut Page, pp: &PagePtr) {
+        self.log_page(p);
         if !p.is_dirty {
             p.is_dirty = true;
             self.dirty_pages.borrow_mut().insert(p.pnum, pp.clone());
@@ -303,11 +468,11 @@ impl SortedFile {
     }
 
     #[cfg(feature = "pack")]
-    /// Attempt to free up logical pages by re-packing child pages.
-    pub fn repack(&self, db: &DB, r: &dyn Record) -> i64 {
-        let mut freed = 0;
-        self.repack_page(db, self.root_page.get(), r, &mut freed);
-        freed
+    /// Attempt to free up logical pages by re-packing child pages, stopping once a limit of the budget is reached.
+    /// Result is the number of pages freed by the budget so far.
+    pub fn repack(&self, db: &DB, r: &dyn Record, b: &mut RepackBudget) -> i64 {
+        self.repack_page(db, self.root_page.get(), r, b);
+        b.freed
     }
 
     /* Notes on repacking.
@@ -318,24 +483,28 @@ impl SortedFile {
 
     #[cfg(feature = "pack")]
     /// Repack a page. Result is number of pages freed.
-    fn repack_page(&self, db: &DB, pnum: u64, r: &dyn Record, freed: &mut i64) {
+    fn repack_page(&self, db: &DB, pnum: u64, r: &dyn Record, b: &mut RepackBudget) {
+        if b.done() {
+            return;
+        }
         let pp = self.load_page(db, pnum);
         let p = &mut pp.borrow_mut();
 
-        if p.level == 0 {
+        if p.level == 0 || p.level == 1 && b.skip_level1() {
             return;
         }
 
         if p.level > 1 {
-            self.repack_page(db, p.first_page, r, freed);
-            if *freed >= REPACK_LIMIT {
-                return;
-            }
-            self.repack_children(db, p, p.root, r, freed);
-            if *freed >= REPACK_LIMIT {
+            self.repack_child(db, p, p.first_page, r, b);
+            self.repack_children(db, p, p.root, r, b);
+            // If there are still pages to skip, this page was repacked by an earlier call.
+            if b.done() || b.skip > 0 {
                 return;
             }
+        } else {
+            b.passed += 1;
         }
+        b.visited += 1;
 
         let (x, y) = Self::page_total(db, p, p.root);
         let n = 1 + x;
@@ -356,6 +525,11 @@ impl SortedFile {
         plist.add(db, p.first_page, r, self, None, 0);
         self.move_children(db, p, p.root, r, &mut plist);
         let n1 = plist.list.len();
+        b.visited += n as i64;
+        if p.level == 2 {
+            // Level 1 pages were merged, keep the count of level 1 pages passed consistent with the new pages.
+            b.passed -= n - n1;
+        }
 
         if TRACE_PACK {
             println!(
@@ -374,7 +548,7 @@ impl SortedFile {
                 plist.packed_record_count
             );
         }
-        *freed += plist.store_to(db, p, self);
+        b.freed += plist.store_to(db, p, self);
     }
 
     #[cfg(feature = "pack")]
@@ -405,17 +579,19 @@ impl SortedFile {
     }
 
     #[cfg(feature = "pack")]
-    fn repack_children(&self, db: &DB, p: &Page, x: usize, r: &dyn Record, freed: &mut i64) {
-        if x != 0 {
-            self.repack_page(db, p.child_page(x), r, freed);
-            if *freed >= REPACK_LIMIT {
-                return;
-            }
-            self.repack_children(db, p, p.left(x), r, freed);
-            if *freed >= REPACK_LIMIT {
-                return;
-            }
-            self.repack_children(db, p, p.right(x), r, freed);
+    fn repack_children(&self, db: &DB, p: &Page, x: usize, r: &dyn Record, b: &mut RepackBudget) {
+        if x != 0 && !b.done() {
+            self.repack_children(db, p, p.left(x), r, b);
+            self.repack_child(db, p, p.child_page(x), r, b);
+            self.repack_children(db, p, p.right(x), r, b);
+        }
+    }
+
+    #[cfg(feature = "pack")]
+    /// Repack a child page, level 1 pages already repacked are skipped without being loaded.
+    fn repack_child(&self, db: &DB, p: &Page, cp: u64, r: &dyn Record, b: &mut RepackBudget) {
+        if p.level != 2 || !b.skip_level1() {
+            self.repack_page(db, cp, r, b);
         }
     }
 
@@ -454,6 +630,8 @@ impl SortedFile {
     /// Renumber, adjusting cache.
     pub fn ren(&self, from: u64, db: &DB) -> u64 {
         let to = db.apd.renumber_page(from);
+        self.log_pnum(from);
+        self.log_pnum(to);
         let mut dp = self.dirty_pages.borrow_mut();
         if let Some(p) = dp.remove(&from) {
             {
@@ -477,6 +655,7 @@ impl SortedFile {
         let pp = self.load_page(db, pnum);
         let p = &mut pp.borrow_mut();
         if p.level != 0 {
+            self.log_page(p);
             if p.first_page >= target {
                 p.first_page = self.ren(p.first_page, db);
                 self.set_dirty(p, &pp);
@@ -515,6 +694,54 @@ impl SortedFile {
 struct ParentInfo<'a> {
     pnum: u64,
     parent: Option<&'a ParentInfo<'a>>,
+    edge: Edge,
+}
+
+/// Which edges of its level a page is on, used to detect keys being inserted in order.
+#[derive(Clone, Copy)]
+struct Edge {
+    /// Page holds the greatest records.
+    high: bool,
+    /// Page holds the least records.
+    low: bool,
+}
+
+impl Edge {
+    /// The root page is on both edges.
+    const BOTH: Edge = Edge {
+        high: true,
+        low: true,
+    };
+
+    /// Edges of child page for node x of p ( zero for first_page ).
+    fn child(self, p: &Page, x: usize) -> Edge {
+        Edge {
+            high: self.high && x == 0,
+            low: self.low && x == p.least(),
+        }
+    }
+
+    /// How to split the full page p when inserting r.
+    fn mode(self, db: &DB, p: &Page, r: &dyn Record) -> SplitMode {
+        if self.high && p.is_greatest(db, r) {
+            SplitMode::Ascending
+        } else if self.low && p.is_least(db, r) {
+            SplitMode::Descending
+        } else {
+            SplitMode::Even
+        }
+    }
+}
+
+/// How to divide a full page.
+#[derive(Clone, Copy)]
+enum SplitMode {
+    /// Divide the records evenly.
+    Even,
+    /// Keys are being inserted in ascending order, left only gets the greatest record.
+    Ascending,
+    /// Keys are being inserted in descending order, right only gets the least record.
+    Descending,
 }
 
 /// For dividing full pages into two.
@@ -526,11 +753,13 @@ struct Split {
     half_page_size: usize,
     left_full: bool,
     got_split: bool,
+    mode: SplitMode,
 }
 
 impl Split {
     /// Split the records of p into two new pages.
-    fn new(p: &mut Page, db: &DB) -> Self {
+    /// When keys are being inserted in order, the page that is not receiving new keys is left nearly full.
+    fn new(p: &mut Page, db: &DB, mode: SplitMode) -> Self {
         let half_page_size = db.apd.spd.psi.half_size_page();
         let mut result = Split {
             count: 0,
@@ -540,6 +769,7 @@ impl Split {
             half_page_size,
             left_full: false,
             got_split: false,
+            mode,
         };
         result.left.first_page = p.first_page;
         result.split(p, p.root);
@@ -550,10 +780,12 @@ impl Split {
     fn split(&mut self, p: &Page, x: usize) {
         if x != 0 {
             self.split(p, p.left(x));
-            if !self.left_full
-                && !self.left.full(self.half_page_size)
-                && self.left.count + 1 < p.count
-            {
+            let to_left = match self.mode {
+                SplitMode::Even => !self.left.full(self.half_page_size),
+                SplitMode::Ascending => self.left.count == 0,
+                SplitMode::Descending => true,
+            };
+            if !self.left_full && to_left && self.left.count + 1 < p.count {
                 self.left.append_from(p, x);
             } else {
                 self.left_full = true;
@@ -743,20 +975,20 @@ impl Stack {
         }
     }
 
-    /// Returns true if a node is found which is <= start.
-    /// This is used to decide whether the the preceding child page is added.
-    fn seek_dsc(&mut self, p: &Page, pp: &PagePtr, mut x: usize) -> bool {
+    /// Seek descending order. Nodes <= start are pushed.
+    /// Result is the smallest node > start ( zero if there is none ), or None if a node equal to start is found.
+    /// For a parent page, the child page of this node ( or the first page if zero ) may hold records <= start.
+    fn seek_dsc(&mut self, p: &Page, pp: &PagePtr, mut x: usize) -> Option<usize> {
+        let mut next = 0;
         while x != 0 {
             match p.compare(&self.db, &*self.start, x) {
                 Ordering::Less => {
-                    if !self.seek_dsc(p, pp, p.right(x)) && p.level != 0 {
-                        self.push(pp, x);
-                    }
-                    return true;
+                    next = x;
+                    x = p.right(x);
                 }
                 Ordering::Equal => {
                     self.push(pp, x);
-                    return true;
+                    return None;
                 }
                 Ordering::Greater => {
                     self.push(pp, x);
@@ -764,7 +996,7 @@ impl Stack {
                 }
             }
         }
-        false
+        Some(next)
     }
 
     fn add_asc(&mut self, p: &Page, pp: &PagePtr, mut x: usize) {
@@ -801,8 +1033,16 @@ impl Stack {
             let p = &pp.borrow();
             let root = p.root;
             if self.seeking {
-                if self.seek_dsc(p, &pp, root) {
-                    return;
+                match self.seek_dsc(p, &pp, root) {
+                    None => return,
+                    Some(0) => {}
+                    Some(x) => {
+                        if p.level == 0 {
+                            return;
+                        }
+                        pnum = p.child_page(x);
+                        continue;
+                    }
                 }
             } else {
                 self.add_dsc(p, &pp, root);
@@ -841,8 +1081,56 @@ struct PageList {
 const TRACE_PACK: bool = false;
 
 #[cfg(feature = "pack")]
-/// Limit on how many pages to free in one transaction.
-const REPACK_LIMIT: i64 = 100;
+/// Limit on how many pages REPACKFILE frees in one transaction.
+pub const REPACK_LIMIT: i64 = 100;
+
+#[cfg(feature = "pack")]
+/// Limits and progress of repacking ( see [SortedFile::repack] ).
+/// Level 1 pages are repacked in key order, so a later call can carry on by skipping the pages already passed.
+pub struct RepackBudget {
+    /// Stop once this many pages have been freed.
+    pub free_limit: i64,
+    /// Stop once this many pages have been visited ( may be exceeded by the child pages of one page ).
+    pub visit_limit: i64,
+    /// Number of level 1 pages still to be skipped.
+    pub skip: usize,
+    /// Number of pages freed.
+    pub freed: i64,
+    /// Number of pages visited.
+    pub visited: i64,
+    /// Number of level 1 pages passed ( skipped or repacked ).
+    pub passed: usize,
+}
+
+#[cfg(feature = "pack")]
+impl RepackBudget {
+    /// Construct a new RepackBudget.
+    pub fn new(free_limit: i64, visit_limit: i64) -> Self {
+        Self {
+            free_limit,
+            visit_limit,
+            skip: 0,
+            freed: 0,
+            visited: 0,
+            passed: 0,
+        }
+    }
+
+    /// Has a limit been reached?
+    pub fn done(&self) -> bool {
+        self.freed >= self.free_limit || self.visited >= self.visit_limit
+    }
+
+    /// Skip a level 1 page if it was passed by an earlier call. Result is true if the page is skipped.
+    fn skip_level1(&mut self) -> bool {
+        if self.skip == 0 {
+            return false;
+        }
+        self.skip -= 1;
+        self.passed += 1;
+        true
+    }
+}
 
 #[cfg(feature = "pack")]
 impl PageList {
@@ -863,7 +1151,7 @@ impl PageList {
             self.list.push((p.new_page(8192), PKey::None));
         }
         self.pnums.push(pnum);
-        file.remove_page(pnum);
+        file.remove_page(p);
         p.pnum = u64::MAX;
 
         if p.level > 0
@@ -929,6 +1217,7 @@ impl PageList {
         }
 
         let pnum = p.pnum;
+        file.log_page(p);
         p.pnum = u64::MAX;
         file.publish_page(pnum, np);
 
@@ -50,6 +50,18 @@ pub fn create_table(db: &DB, info: &ColInfo) {
     }
 }
 
+/// Flag added to ColId in the system IndexColumn table for INCLUDE columns.
+const INCLUDE: i64 = 1 << 32;
+
+/// Flag added to ColId in the system IndexColumn table for key expressions ( ColId is then the Id in sys.IndexExp ).
+const EXPRESSION: i64 = 1 << 33;
+
+/// Flag added to ColId in the system IndexColumn table for descending key columns.
+const DESCENDING: i64 = 1 << 34;
+
+/// System tables with extra index information ( Index, Def ), created when first required.
+pub(crate) const INDEX_TABLES: [&str; 4] = ["IndexFilter", "IndexExp", "IndexStats", "IndexBuild"];
+
 /// Create a new table index by writing to the system Index and IndexColumn tables.
 pub fn create_index(db: &DB, info: &IndexInfo) {
     if let Some(table) = db.get_table(&info.tname) {
@@ -68,23 +80,149 @@ pub fn create_index(db: &DB, info: &IndexInfo) {
         {
             let t = &db.sys_index_col;
             let mut row = t.row();
-            for cnum in &info.cols {
+            for (cnum, desc) in info.cols.iter().zip(&info.desc) {
+                let mut col = if *cnum >= EXP_COL {
+                    let source = &info.exps[*cnum - EXP_COL];
+                    EXPRESSION + save_index_info(db, "IndexExp", index_id, source)
+                } else {
+                    *cnum as i64
+                };
+                if *desc {
+                    col += DESCENDING;
+                }
                 // Columns are Index, ColIndex
                 row.id = t.alloc_id(db);
                 row.values[0] = Value::Int(index_id);
-                row.values[1] = Value::Int(*cnum as i64);
+                row.values[1] = Value::Int(col);
+                t.insert(db, &mut row);
+            }
+            for cnum in &info.include {
+                row.id = t.alloc_id(db);
+                row.values[0] = Value::Int(index_id);
+                row.values[1] = Value::Int(*cnum as i64 + INCLUDE);
                 t.insert(db, &mut row);
             }
         }
+        if let Some(source) = &info.filter {
+            save_index_info(db, "IndexFilter", index_id, source);
+        }
         if root > SYS_ROOT_LAST {
-            table.add_index(root, info.cols.clone(), index_id);
-            table.init_index(db);
+            let exps = info
+                .exps
+                .iter()
+                .map(|source| parse_exp(db, &table, source))
+                .collect();
+            let (cols, include) = (info.cols.clone(), info.include.clone());
+            table.add_exp_index(root, cols, include, exps, index_id);
+            table.set_desc(&info.desc);
+            if let Some(source) = &info.filter {
+                table.set_filter(parse_filter(db, &table, source));
+            }
+            if info.online {
+                // Rows are added to the index in steps, see Table::build_index.
+                assert!(
+                    table.info.key.is_empty(),
+                    "ONLINE index not supported for table with PRIMARY KEY"
+                );
+                save_index_info(db, "IndexBuild", index_id, "0");
+                table.set_build(0);
+            } else {
+                table.init_index(db);
+            }
         }
+        db.schema_gen.set(db.schema_gen.get() + 1);
     } else {
         panic!("table not found: {}", &info.tname.str());
     }
 }
 
+/// Get one of the system [INDEX_TABLES] ( IndexFilter holds WHERE conditions of partial indexes,
+/// IndexExp holds key expressions, IndexStats holds statistics, IndexBuild holds the progress of ONLINE index builds ),
+/// creating it if required.
+pub(crate) fn index_table(db: &DB, name: &str, create: bool) -> Option<Rc<Table>> {
+    let name = ObjRef::new("sys", name);
+    if let Some(t) = db.get_table(&name) {
+        return Some(t);
+    }
+    if !create {
+        return None;
+    }
+    // Columns are Index, Def. There is no index, as the table is only read when a table is loaded.
+    let mut info = ColInfo::empty(name.clone());
+    info.add("Index".to_string(), INT);
+    info.add("Def".to_string(), BIGSTR);
+    create_table(db, &info);
+    db.get_table(&name)
+}
+
+/// Save extra index information ( source SQL ), result is the Id of the saved row.
+fn save_index_info(db: &DB, name: &str, index_id: i64, source: &str) -> i64 {
+    let t = index_table(db, name, true).unwrap();
+    let mut row = t.row();
+    // Columns are Index, Def
+    row.id = t.alloc_id(db);
+    row.values[0] = Value::Int(index_id);
+    row.values[1] = Value::String(Rc::new(source.to_string()));
+    t.insert(db, &mut row);
+    row.id
+}
+
+/// Get the WHERE condition of a partial index.
+fn get_index_filter(db: &DB, index_id: i64) -> Option<String> {
+    get_index_info(db, "IndexFilter", index_id)
+}
+
+/// Save index statistics.
+/// Def is the number of table rows, the number of index records, then the distinct key counts.
+pub(crate) fn save_index_stats(db: &DB, index_id: i64, stats: &IndexStats) {
+    let mut def = format!("{} {}", stats.rows, stats.records);
+    for d in &stats.distinct {
+        def += &format!(" {}", d);
+    }
+    save_index_info(db, "IndexStats", index_id, &def);
+}
+
+/// Get index statistics ( see [save_index_stats] ).
+fn get_index_stats(db: &DB, index_id: i64) -> Option<IndexStats> {
+    let def = get_index_info(db, "IndexStats", index_id)?;
+    let mut n = def.split(' ').map(|x| x.parse::<u64>().unwrap());
+    let (rows, records) = (n.next().unwrap(), n.next().unwrap());
+    Some(IndexStats::new(rows, records, n.collect()))
+}
+
+/// Get extra index information from one of the [INDEX_TABLES].
+fn get_index_info(db: &DB, name: &str, index_id: i64) -> Option<String> {
+    let t = index_table(db, name, false)?;
+    for (pp, off) in t.scan(db) {
+        let p = &pp.borrow();
+        let a = t.access(p, off);
+        if a.int(0) == index_id {
+            return Some(a.str(db, 1));
+        }
+    }
+    None
+}
+
+/// Get the source of an index key expression.
+fn get_index_exp(db: &DB, id: i64) -> String {
+    let t = index_table(db, "IndexExp", false).unwrap();
+    let (pp, off) = t.id_get(db, id as u64).unwrap();
+    let p = &pp.borrow();
+    t.access(p, off).str(db, 1)
+}
+
+/// Parse an index key expression.
+fn parse_exp(db: &DB, table: &Rc<Table>, source: &str) -> IndexExp {
+    let mut p = Parser::new(source, db);
+    p.parse_index_exp(table)
+}
+
+/// Parse the WHERE condition of a partial index.
+fn parse_filter(db: &DB, table: &Rc<Table>, source: &str) -> Vec<FilterCond> {
+    let mut p = Parser::new(source, db);
+    p.parse_index_filter(table)
+}
+
 /// Create or alter a function in the database by saving the source into the Function system table.
 pub fn create_function(db: &DB, name: &ObjRef, source: Rc<String>, alter: bool) {
     if let Some(schema_id) = get_schema(db, &name.schema) {
@@ -103,9 +241,9 @@ pub fn create_function(db: &DB, name: &ObjRef, source: Rc<String>, alter: bool)
                     db.delcode(oldcode);
                     let val = Value::String(source);
                     let newcode = db.encode(&val, data_size(BIGSTR));
-                    val.save(BIGSTR, &mut p.data, off, newcode);
                     t.file.set_dirty(p, &pp);
-                    db.function_reset.set(true);
+                    val.save(BIGSTR, &mut p.data, off, newcode);
+                    db.schema_changed();
                 }
                 return;
             }
@@ -146,6 +284,76 @@ pub fn get_schema(db: &DB, sname: &str) -> Option<i64> {
     None
 }
 
+/// Get the ids of the tables in a schema.
+pub(crate) fn get_schema_tables(db: &DB, schema_id: i64) -> Vec<i64> {
+    let t = &db.sys_table;
+    // Columns are root, schema, name, id_gen
+    let mut result = Vec::new();
+    for (pp, off) in t.scan_key(db, Value::Int(schema_id), 0) {
+        let p = &pp.borrow();
+        result.push(t.access(p, off).id() as i64);
+    }
+    result
+}
+
+/// Get the ids of the indexes of a table.
+pub(crate) fn get_table_indexes(db: &DB, table_id: i64) -> Vec<i64> {
+    let t = &db.sys_index;
+    // Columns are Root, Table, Name
+    let mut result = Vec::new();
+    for (pp, off) in t.scan_key(db, Value::Int(table_id), 0) {
+        let p = &pp.borrow();
+        result.push(t.access(p, off).id() as i64);
+    }
+    result
+}
+
+/// Get the names of the tables with indexes being built ONLINE.
+pub(crate) fn get_index_build_tables(db: &DB) -> Vec<ObjRef> {
+    let mut result = Vec::new();
+    if let Some(t) = index_table(db, "IndexBuild", false) {
+        let mut indexes = Vec::new();
+        for (pp, off) in t.scan(db) {
+            let p = &pp.borrow();
+            // Columns are Index, Def
+            indexes.push(t.access(p, off).int(0));
+        }
+        for index_id in indexes {
+            if let Some(name) = get_index_table_name(db, index_id) {
+                if !result.contains(&name) {
+                    result.push(name);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Get the name of the table of an index.
+fn get_index_table_name(db: &DB, index_id: i64) -> Option<ObjRef> {
+    let table_id = {
+        let t = &db.sys_index;
+        let (pp, off) = t.id_get(db, index_id as u64)?;
+        let p = &pp.borrow();
+        // Columns are Root, Table, Name
+        t.access(p, off).int(1)
+    };
+    let (schema_id, tname) = {
+        let t = &db.sys_table;
+        let (pp, off) = t.id_get(db, table_id as u64)?;
+        let p = &pp.borrow();
+        let a = t.access(p, off);
+        // Columns are root, schema, name, id_gen
+        (a.int(1), a.str(db, 2))
+    };
+    let t = &db.sys_schema;
+    let (pp, off) = t.id_get(db, schema_id as u64)?;
+    let p = &pp.borrow();
+    // Columns are Name
+    let sname = t.access(p, off).str(db, 0);
+    Some(ObjRef::new(&sname, &tname))
+}
+
 /// Get the id, root, id_gen for specified table.
 fn get_table0(db: &DB, name: &ObjRef) -> Option<(i64, i64, i64)> {
     if let Some(schema_id) = get_schema(db, &name.schema) {
@@ -184,6 +392,18 @@ pub fn get_index(db: &DB, tname: &ObjRef, iname: &str) -> (Rc<Table>, usize, u64
     }
 }
 
+/// Gets the name of an index.
+pub fn get_index_name(db: &DB, id: i64) -> String {
+    let t = &db.sys_index;
+    if let Some((pp, off)) = t.id_get(db, id as u64) {
+        let p = &pp.borrow();
+        // Columns are Root, Table, Name.
+        t.access(p, off).str(db, 2)
+    } else {
+        String::new()
+    }
+}
+
 /// Gets table from the database.
 pub fn get_table(db: &DB, name: &ObjRef) -> Option<Rc<Table>> {
     if let Some((table_id, root, id_gen)) = get_table0(db, name) {
@@ -210,6 +430,9 @@ pub fn get_table(db: &DB, name: &ObjRef) -> Option<Rc<Table>> {
             let index_id = a.id() as i64;
             let root = a.int(0) as u64;
             let mut cols = Vec::new();
+            let mut include = Vec::new();
+            let mut exps = Vec::new();
+            let mut desc = Vec::new();
             let t = &db.sys_index_col;
             // Columns are Index, ColIndex
             let key = Value::Int(index_id);
@@ -217,10 +440,33 @@ pub fn get_table(db: &DB, name: &ObjRef) -> Option<Rc<Table>> {
                 let p = &pp.borrow();
                 let a = t.access(p, off);
                 debug_assert!(a.int(0) == index_id);
-                let cnum = a.int(1) as usize;
-                cols.push(cnum);
+                let cnum = a.int(1);
+                if cnum & INCLUDE != 0 {
+                    include.push((cnum - INCLUDE) as usize);
+                    continue;
+                }
+                desc.push(cnum & DESCENDING != 0);
+                let cnum = cnum & !DESCENDING;
+                if cnum & EXPRESSION != 0 {
+                    let source = get_index_exp(db, cnum - EXPRESSION);
+                    cols.push(EXP_COL + exps.len());
+                    exps.push(parse_exp(db, &table, &source));
+                } else {
+                    cols.push(cnum as usize);
+                }
+            }
+            table.add_exp_index(root, cols, include, exps, index_id);
+            table.set_desc(&desc);
+            if let Some(source) = get_index_filter(db, index_id) {
+                table.set_filter(parse_filter(db, &table, &source));
+            }
+            if let Some(stats) = get_index_stats(db, index_id) {
+                let index = table.ixlist.borrow().len() - 1;
+                table.set_stats(index, stats);
+            }
+            if let Some(progress) = get_index_info(db, "IndexBuild", index_id) {
+                table.set_build(progress.parse().unwrap());
             }
-            table.add_index(root, cols, index_id);
         }
         db.publish_table(table.clone());
         Some(table)
@@ -229,6 +475,24 @@ pub fn get_table(db: &DB, name: &ObjRef) -> Option<Rc<Table>> {
     }
 }
 
+/// Reload the progress of ONLINE index builds for a loaded table ( after a rollback ).
+pub(crate) fn reload_index_builds(db: &DB, table: &Table) {
+    let builds: Vec<(usize, u64)> = table
+        .ixlist
+        .borrow()
+        .iter()
+        .enumerate()
+        .filter_map(|(i, ix)| {
+            let progress = get_index_info(db, "IndexBuild", ix.id)?;
+            Some((i, progress.parse().unwrap()))
+        })
+        .collect();
+    let list = &mut *table.ixlist.borrow_mut();
+    for (i, progress) in builds {
+        list[i].build = Some(progress);
+    }
+}
+
 /// Get then parse a function from the database.
 pub fn get_function(db: &DB, name: &ObjRef) -> Option<Rc<Function>> {
     if let Some(schema_id) = get_schema(db, &name.schema) {
@@ -241,7 +505,7 @@ pub fn get_function(db: &DB, name: &ObjRef) -> Option<Rc<Function>> {
             let p = &pp.borrow();
             let a = t.access(p, off);
             let source = Rc::new(a.str(db, 2));
-            let function = parse_function(db, source);
+            let function = parse_function(db, name, source);
             db.functions
                 .borrow_mut()
                 .insert(name.clone(), function.clone());
@@ -269,13 +533,15 @@ pub fn get_function_id(db: &DB, name: &ObjRef) -> Option<i64> {
 }
 
 /// Parse a function definition.
-fn parse_function(db: &DB, source: Rc<String>) -> Rc<Function> {
+fn parse_function(db: &DB, name: &ObjRef, source: Rc<String>) -> Rc<Function> {
     let mut p = Parser::new(&source, db);
     p.b.parse_only = true;
     p.parse_function();
     Rc::new(Function {
         compiled: Cell::new(false),
         ilist: RefCell::new(Vec::new()),
+        lines: RefCell::new(Vec::new()),
+        name: name.clone(),
         local_typ: p.b.local_typ,
         return_type: p.b.return_type,
         param_count: p.b.param_count,
@@ -298,10 +564,10 @@ pub fn save_id_gen(db: &DB, id: u64, val: i64) {
     let t = &db.sys_table;
     let (pp, off) = t.id_get(db, id).unwrap();
     let p = &mut pp.borrow_mut();
+    t.file.set_dirty(p, &pp);
     let mut wa = t.write_access(p, off);
     debug_assert!(wa.id() == id);
     wa.set_int(3, val);
-    t.file.set_dirty(p, &pp);
 }
 
 /// Update root page for table ( for ALTER TABLE ).
@@ -310,10 +576,10 @@ pub fn set_root(db: &DB, id: i64, new_root: u64) {
     let t = &db.sys_table;
     let (pp, off) = t.id_get(db, id).unwrap();
     let p = &mut pp.borrow_mut();
+    t.file.set_dirty(p, &pp);
     let mut wa = t.write_access(p, off);
     debug_assert!(wa.id() == id);
     wa.set_int(0, new_root as i64);
-    t.file.set_dirty(p, &pp);
 }
 
 /// Update root page for index.
@@ -323,8 +589,8 @@ pub fn set_ix_root(db: &DB, id: i64, new_root: u64) {
     let t = &db.sys_index;
     let (pp, off) = t.id_get(db, id).unwrap();
     let p = &mut pp.borrow_mut();
+    t.file.set_dirty(p, &pp);
     let mut wa = t.write_access(p, off);
     debug_assert!(wa.id() == id);
     wa.set_int(0, new_root as i64);
-    t.file.set_dirty(p, &pp);
 }
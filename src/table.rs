@@ -1,18 +1,142 @@
 use crate::*;
 
 /// Table Index.
+#[derive(Clone)]
 pub struct Index {
     /// File.
     pub file: Rc<SortedFile>,
-    /// columns.
+    /// columns ( numbers from [EXP_COL] are expressions ).
     pub cols: Rc<Vec<usize>>,
+    /// INCLUDE columns ( values are stored in the index records, but are not part of the key ).
+    pub include: Rc<Vec<usize>>,
+    /// Types of the key columns followed by the INCLUDE columns.
+    pub typ: Rc<Vec<DataType>>,
+    /// Key expressions.
+    pub exps: Rc<Vec<IndexExp>>,
+    /// WHERE conditions of a partial index ( only rows that satisfy all the conditions are indexed ).
+    pub filter: Rc<Vec<FilterCond>>,
+    /// Statistics collected by ANALYZE.
+    pub stats: Option<Rc<IndexStats>>,
+    /// Offsets of the PRIMARY KEY values in the index records ( empty if the table has no PRIMARY KEY ).
+    pub pk: Rc<Vec<usize>>,
+    /// For an index being built ONLINE, rows with Id less than this have been added to the index.
+    /// The index is not used for queries until the build is complete.
+    pub build: Option<u64>,
     /// Index id.
     pub id: i64,
 }
 
+impl Index {
+    /// Does the row satisfy the WHERE conditions of the index ( and has the index build reached the row )?
+    fn includes(&self, info: &ColInfo, row: &Row) -> bool {
+        self.build.is_none_or(|b| (row.id as u64) < b)
+            && self
+                .filter
+                .iter()
+                .all(|(col, op, k)| compare(&row.values[*col], *op, k, info.typ[*col]))
+    }
+
+    /// Get the expression for an index column number.
+    fn exp(&self, col: usize) -> &IndexExp {
+        self.exps.iter().find(|x| x.col == col).unwrap()
+    }
+
+    /// Get the name of a key column ( the text for an expression ).
+    pub fn col_name<'a>(&'a self, info: &'a ColInfo, col: usize) -> &'a str {
+        if col < EXP_COL {
+            &info.colnames[col]
+        } else {
+            &self.exp(col).text
+        }
+    }
+
+    /// Get the position and type of each key expression with a string or binary value ( stored values may have codes ).
+    fn coded_exps(&self) -> Vec<(usize, DataType)> {
+        let mut result = Vec::new();
+        for (pos, col) in self.cols.iter().enumerate() {
+            let typ = self.typ[pos];
+            if *col >= EXP_COL && matches!(data_kind(typ), DataKind::String | DataKind::Binary) {
+                result.push((pos, typ));
+            }
+        }
+        result
+    }
+
+    /// Get the codes of key expression values in an index record.
+    fn exp_codes(&self, db: &DB, data: &[u8], list: &[(usize, DataType)], codes: &mut Vec<Code>) {
+        for (pos, typ) in list {
+            let off = 8 + self.typ[0..*pos]
+                .iter()
+                .map(|t| data_size(*t))
+                .sum::<usize>();
+            let code = Value::load(db, *typ, data, off).1;
+            if code.id != u64::MAX {
+                codes.push(code);
+            }
+        }
+    }
+}
+
+/// Index column numbers from EXP_COL are key expressions ( see [Index::exps] ).
+pub const EXP_COL: usize = 1 << 30;
+
+/// Key expression of an index.
+#[non_exhaustive]
+pub struct IndexExp {
+    /// Column number used for the expression ( the same expression has the same number in all the indexes of a table ).
+    pub col: usize,
+    /// Expression text, used to match expressions in WHERE conditions ( see [explain::exp_text] ).
+    pub text: String,
+    /// Type of the expression.
+    pub typ: DataType,
+    /// Compiled expression, evaluated for table records.
+    pub exp: CExpPtr<Value>,
+}
+
+/// Condition of a partial index : column, comparison operator and constant.
+pub type FilterCond = (usize, Token, Value);
+
+/// Index statistics, collected by ANALYZE and used to choose indexes.
+#[non_exhaustive]
+pub struct IndexStats {
+    /// Number of table rows.
+    pub rows: u64,
+    /// Number of index records ( fewer than rows for a partial index ).
+    pub records: u64,
+    /// Number of distinct values of the first i + 1 key columns, for each i.
+    pub distinct: Vec<u64>,
+}
+
+impl IndexStats {
+    /// Construct IndexStats.
+    pub fn new(rows: u64, records: u64, distinct: Vec<u64>) -> Self {
+        Self {
+            rows,
+            records,
+            distinct,
+        }
+    }
+
+    /// Estimated cost of reading the rows with the first n key column values fixed.
+    fn cost(&self, n: usize) -> u64 {
+        self.records.div_ceil(self.distinct[n - 1].max(1)) * LOOKUP_COST
+    }
+}
+
+/// Cost of fetching a row using an index, relative to the cost of reading a row in a full scan.
+const LOOKUP_COST: u64 = 3;
+
 /// List of indexes. Each index has a file and a list of column numbers.
 pub type IxList = Vec<Index>;
 
+/// Unsaved state of a [Table] ( see [Table::state] ).
+pub struct TableState {
+    file: FileState,
+    ixlist: Vec<(Index, FileState)>,
+    id_gen: Option<i64>,
+    id_gen_dirty: bool,
+}
+
 /// Save or Rollback.
 #[derive(PartialEq, Eq, PartialOrd, Clone, Copy)]
 pub enum SaveOp {
@@ -41,13 +165,16 @@ pub struct Table {
 
     /// Row id allocator has changed.
     pub id_gen_dirty: Cell<bool>,
+
+    /// Triggers ( loaded when first required ).
+    pub(crate) triggers: RefCell<Option<Rc<trigger::TriggerList>>>,
 }
 
 impl Table {
     /// Construct a table with specified info.
     pub fn new(id: i64, root_page: u64, id_gen: i64, info: Rc<ColInfo>) -> Rc<Table> {
         let rec_size = info.total;
-        let key_size = 8;
+        let key_size = info.key_size();
         let file = Rc::new(SortedFile::new(rec_size, key_size, root_page));
         let ixlist = RefCell::new(Vec::new());
         Rc::new(Table {
@@ -57,6 +184,7 @@ impl Table {
             ixlist,
             id_gen: Cell::new(Some(id_gen)),
             id_gen_dirty: Cell::new(false),
+            triggers: RefCell::new(None),
         })
     }
 
@@ -68,23 +196,86 @@ impl Table {
         }
     }
 
+    /// Record unsaved state ( for a savepoint ).
+    pub fn state(&self) -> TableState {
+        let ixlist = self.ixlist.borrow();
+        TableState {
+            file: self.file.state(),
+            ixlist: ixlist
+                .iter()
+                .map(|ix| (ix.clone(), ix.file.state()))
+                .collect(),
+            id_gen: self.id_gen.get(),
+            id_gen_dirty: self.id_gen_dirty.get(),
+        }
+    }
+
+    /// Restore unsaved state from a savepoint.
+    pub fn restore(&self, s: &TableState) {
+        self.file.restore(&s.file);
+        let ixlist = &mut *self.ixlist.borrow_mut();
+        ixlist.clear();
+        for (ix, fs) in &s.ixlist {
+            ix.file.restore(fs);
+            ixlist.push(ix.clone());
+        }
+        self.id_gen.set(s.id_gen);
+        self.id_gen_dirty.set(s.id_gen_dirty);
+    }
+
+    /// Release state recorded for a savepoint that is no longer needed.
+    pub fn release(&self, s: &TableState) {
+        self.file.release(&s.file);
+        for (ix, fs) in &s.ixlist {
+            ix.file.release(fs);
+        }
+    }
+
     /// Drop the underlying file storage ( the table is not useable after this ).
     pub fn free_pages(&self, db: &DB) {
         let row = self.row();
         self.file.free_pages(db, &row);
         for ix in &*self.ixlist.borrow() {
-            let ixr = IndexRow::new(self, ix.cols.clone(), &row);
+            self.free_exp_codes(db, ix);
+            let ixr = IndexRow::new(db, self, ix, &row);
             ix.file.free_pages(db, &ixr);
         }
     }
 
+    /// Delete the codes of key expression values ( before the index is dropped ).
+    fn free_exp_codes(&self, db: &DB, ix: &Index) {
+        let list = ix.coded_exps();
+        if list.is_empty() {
+            return;
+        }
+        let mut codes = Vec::new();
+        for (pp, off) in ix.file.asc(db, Box::new(Zero {})) {
+            let p = pp.borrow();
+            ix.exp_codes(db, &p.data[off..], &list, &mut codes);
+        }
+        for code in codes {
+            db.delcode(code);
+        }
+    }
+
     /// Insert specified row into the table.
     pub fn insert(&self, db: &DB, row: &mut Row) {
+        if !self.info.key.is_empty() && self.file.get(db, row).is_some() {
+            panic!("duplicate PRIMARY KEY in table {}", self.info.name.str());
+        }
         row.encode(db); // Calculate codes for Binary and String values.
+        let ixlist = &*self.ixlist.borrow();
+        // Key expressions are evaluated before anything is written, as evaluation may fail.
+        let mut ixrows = Vec::with_capacity(ixlist.len());
+        for ix in ixlist {
+            if ix.includes(&self.info, row) {
+                ixrows.push((ix, IndexRow::new(db, self, ix, row)));
+            }
+        }
         self.file.insert(db, row);
         // Update any indexes.
-        for ix in &*self.ixlist.borrow() {
-            let ixr = IndexRow::new(self, ix.cols.clone(), row);
+        for (ix, mut ixr) in ixrows {
+            ixr.encode_exps(db, ix);
             ix.file.insert(db, &ixr);
         }
     }
@@ -93,8 +284,21 @@ impl Table {
     pub fn remove(&self, db: &DB, row: &Row) {
         self.file.remove(db, row);
         for ix in &*self.ixlist.borrow() {
-            let ixr = IndexRow::new(self, ix.cols.clone(), row);
-            ix.file.remove(db, &ixr);
+            if ix.includes(&self.info, row) {
+                let ixr = IndexRow::new(db, self, ix, row);
+                // Codes for key expression values belong to the index record.
+                let list = ix.coded_exps();
+                let mut codes = Vec::new();
+                if !list.is_empty() {
+                    if let Some((pp, off)) = ix.file.get(db, &ixr) {
+                        ix.exp_codes(db, &pp.borrow().data[off..], &list, &mut codes);
+                    }
+                }
+                ix.file.remove(db, &ixr);
+                for code in codes {
+                    db.delcode(code);
+                }
+            }
         }
         row.delcodes(db); // Deletes codes for Binary and String values.
     }
@@ -105,20 +309,60 @@ impl Table {
         b: &Block,
         we: &mut Expr,
     ) -> (Option<CExpPtr<bool>>, Option<CTableExpression>) {
+        self.mark_exps(b, we);
         let mut kc = SmallSet::default(); // Set of known columns.
         get_known_cols(we, &mut kc);
 
+        // A partial index can only be used if the WHERE conditions of the index are implied by we.
+        let mut qc = Vec::new();
+        query_conds(we, &mut qc);
+        let usable = |ix: &Index| usable(ix, &qc, &self.info);
+
         let list = &*self.ixlist.borrow();
 
+        // If there are statistics ( see ANALYZE ), the index with the lowest estimated cost is chosen,
+        // provided the cost is no more than a full scan. Otherwise the index that matches most columns is chosen.
         let mut best_match = 0;
         let mut best_index = 0;
+        let mut best_cost = u64::MAX;
         for (index, ix) in list.iter().enumerate() {
+            if !usable(ix) {
+                continue;
+            }
             let m = covered(&ix.cols, &kc);
-            if m > best_match {
+            if m == 0 {
+                continue;
+            }
+            if let Some(stats) = &ix.stats {
+                let cost = stats.cost(m);
+                if cost < best_cost && cost <= stats.rows {
+                    best_cost = cost;
+                    best_match = m;
+                    best_index = index;
+                }
+            } else if best_cost == u64::MAX && m > best_match {
                 best_match = m;
                 best_index = index;
             }
         }
+        // Rows of a table with a PRIMARY KEY are located directly, unless an index matches more columns.
+        let pk = covered(&self.info.key, &kc);
+        if pk > 0 && (pk == self.info.key.len() || pk >= best_match) {
+            let mut cols = SmallSet::default();
+            for col in self.info.key.iter().take(pk) {
+                cols.insert(*col);
+            }
+            let mut kmap = BTreeMap::new();
+            let cwe = get_keys(b, we, &mut cols, &mut kmap);
+            let keys = self
+                .info
+                .key
+                .iter()
+                .take(pk)
+                .map(|col| kmap.remove(col).unwrap())
+                .collect();
+            return (cwe, Some(CTableExpression::KeyGet(self.clone(), keys)));
+        }
         if best_match > 0 {
             // Get the key values for the chosen index.
             let clist = &list[best_index].cols;
@@ -139,48 +383,196 @@ impl Table {
             );
         }
 
-        // ToDo: check for mirror expression, AND conditions, also Id = x OR Id = y ...  Id in (....) etc.
-        if let ExprIs::Binary(op, e1, e2) = &mut we.exp {
-            if *op == Token::Equal && e2.is_constant {
-                if let ExprIs::ColName(_) = &e1.exp {
-                    if e1.col == usize::MAX
+        // Look for a condition Id = x, or a key list ( e.g. Id IN ( x, y ), col = x OR col = y )
+        // for Id or the first column of an index.
+        let mut conds = Vec::new();
+        conjuncts(we, &mut conds);
+        for i in 0..conds.len() {
+            if let Some(col) = key_list_col(conds[i]) {
+                let mut keys = Vec::new();
+                key_list(conds[i], &mut keys);
+                let te = if col == usize::MAX {
                     // Id column.
-                    {
-                        return (
-                            None,
-                            Some(CTableExpression::IdGet(self.clone(), c_int(b, e2))),
-                        );
+                    if keys.len() == 1 {
+                        CTableExpression::IdGet(self.clone(), c_int(b, keys[0]))
+                    } else {
+                        let keys = keys.into_iter().map(|k| c_int(b, k)).collect();
+                        CTableExpression::IdList(self.clone(), keys)
+                    }
+                } else if let Some(index) = list.iter().position(|ix| {
+                    ix.cols[0] == col
+                        && usable(ix)
+                        && ix
+                            .stats
+                            .as_ref()
+                            .is_none_or(|s| s.cost(1) * keys.len() as u64 <= s.rows)
+                }) {
+                    let keys = keys.into_iter().map(|k| c_value(b, k)).collect();
+                    CTableExpression::IxList(self.clone(), keys, index)
+                } else {
+                    continue;
+                };
+                // Compile the other conditions.
+                let mut cwe: Option<CExpPtr<bool>> = None;
+                for (j, c) in conds.iter_mut().enumerate() {
+                    if j != i {
+                        let c = c_bool(b, c);
+                        cwe = Some(if let Some(x) = cwe {
+                            Box::new(cexp::And(x, c))
+                        } else {
+                            c
+                        });
                     }
                 }
+                return (cwe, Some(te));
             }
         }
         (Some(c_bool(b, we)), None)
     }
 
-    /// Get record with specified id.
+    /// Set col for sub-expressions of we that match a key expression of an index.
+    fn mark_exps(&self, b: &Block, we: &mut Expr) {
+        let list = &*self.ixlist.borrow();
+        if list.iter().all(|ix| ix.exps.is_empty()) {
+            return;
+        }
+        let text = explain::exp_text(b, we);
+        for ix in list {
+            if let Some(x) = ix.exps.iter().find(|x| x.text == text) {
+                we.col = x.col;
+                return;
+            }
+        }
+        match &mut we.exp {
+            ExprIs::Binary(_, e1, e2) => {
+                self.mark_exps(b, e1);
+                self.mark_exps(b, e2);
+            }
+            ExprIs::List(list) => {
+                for e in list {
+                    self.mark_exps(b, e);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Look for an index that gives rows in ORDER BY order ( so no sort is needed ).
+    /// from is the index and number of key values used for the WHERE condition, if any.
+    /// Result is the index and whether the index is read in reverse order.
+    pub fn index_order(
+        &self,
+        b: &Block,
+        wher: &Option<Expr>,
+        orderby: &mut [(Expr, bool)],
+        from: Option<(usize, usize)>,
+    ) -> Option<(usize, bool)> {
+        for (e, _) in orderby.iter_mut() {
+            self.mark_exps(b, e);
+        }
+        let list = &*self.ixlist.borrow();
+        if let Some((index, n)) = from {
+            let ix = &list[index];
+            return order_rev(&ix.cols, &ix.typ, n, orderby).map(|rev| (index, rev));
+        }
+        let mut qc = Vec::new();
+        if let Some(we) = wher {
+            query_conds(we, &mut qc);
+        }
+        list.iter()
+            .enumerate()
+            .filter(|(_, ix)| usable(ix, &qc, &self.info))
+            .find_map(|(index, ix)| {
+                order_rev(&ix.cols, &ix.typ, 0, orderby).map(|rev| (index, rev))
+            })
+    }
+
+    /// Check whether rows of a table with a PRIMARY KEY are read in ORDER BY order, given that the first n key columns have fixed values.
+    pub fn key_order(&self, orderby: &[(Expr, bool)], n: usize) -> bool {
+        if self.info.key.is_empty() {
+            return false;
+        }
+        // Rows are stored in ascending key order, the same as an index column with DESC ( see key_cmp ).
+        let typ: Vec<DataType> = self
+            .info
+            .key
+            .iter()
+            .map(|c| self.info.typ[*c] | DESC)
+            .collect();
+        order_rev(&self.info.key, &typ, n, orderby) == Some(false)
+    }
+
+    /// Get record with specified id. For a table with a PRIMARY KEY this scans the table, as rows are not ordered by Id.
     pub fn id_get(&self, db: &DB, id: u64) -> Option<(PagePtr, usize)> {
-        self.file.get(db, &Id { id })
+        if self.info.key.is_empty() {
+            self.file.get(db, &Id { id })
+        } else {
+            // Rows are ordered by the PRIMARY KEY, so the table has to be scanned.
+            self.scan(db)
+                .find(|(pp, off)| util::getu64(&pp.borrow().data, *off) == id)
+        }
     }
 
     /// Get record with matching key, using specified index.
     pub fn ix_get(&self, db: &DB, key: Vec<Value>, index: usize) -> Option<(PagePtr, usize)> {
         let list = &*self.ixlist.borrow();
         let ix = &list[index];
-        let key = IndexKey::new(self, ix.cols.clone(), key, Ordering::Equal);
+        let key = IndexKey::new(ix, key, Ordering::Equal);
         if let Some((pp, off)) = ix.file.get(db, &key) {
             let p = pp.borrow();
-            let id = util::getu64(&p.data, off);
-            let row = Id { id };
-            return self.file.get(db, &row);
+            return self.ix_fetch(db, &ix.pk, &p.data[off..]);
         }
         None
     }
 
+    /// Get the record for an index record ( pk is [Index::pk] ).
+    fn ix_fetch(&self, db: &DB, pk: &[usize], data: &[u8]) -> Option<(PagePtr, usize)> {
+        if pk.is_empty() {
+            return self.id_get(db, util::getu64(data, 0));
+        }
+        let key = self
+            .info
+            .key
+            .iter()
+            .zip(pk)
+            .map(|(c, off)| Value::load(db, self.info.typ[*c], data, *off).0)
+            .collect();
+        self.file
+            .get(db, &TableKey::new(&self.info, key, Ordering::Equal))
+    }
+
+    /// Get a key that locates the specified stored record ( the Id, or the PRIMARY KEY values ).
+    pub fn rec_key(&self, db: &DB, data: &[u8]) -> Box<dyn Record> {
+        if self.info.key.is_empty() {
+            return Box::new(Id {
+                id: util::getu64(data, 0),
+            });
+        }
+        let key = self
+            .info
+            .key
+            .iter()
+            .map(|c| Value::load(db, self.info.typ[*c], data, self.info.off[*c]).0)
+            .collect();
+        Box::new(TableKey::new(&self.info, key, Ordering::Equal))
+    }
+
     /// Scan all the records in the table.
     pub fn scan(&self, db: &DB) -> Asc {
         self.file.asc(db, Box::new(Zero {}))
     }
 
+    /// Get records with matching PRIMARY KEY values ( keys may be a prefix of the PRIMARY KEY ).
+    pub fn scan_pk(&self, db: &DB, keys: Vec<Value>) -> KeyScan {
+        let start = TableKey::new(&self.info, keys.clone(), Ordering::Less);
+        KeyScan {
+            asc: self.file.asc(db, Box::new(start)),
+            info: self.info.clone(),
+            db: db.clone(),
+            keys,
+        }
+    }
+
     /// Get a single record with specified id.
     pub fn scan_id(self: &Rc<Table>, db: &DB, id: i64) -> IdScan {
         IdScan {
@@ -199,37 +591,248 @@ impl Table {
 
     /// Get records with matching keys.
     pub fn scan_keys(self: &Rc<Table>, db: &DB, keys: Vec<Value>, index: usize) -> IndexScan {
+        self.scan_index(db, keys, index, false, false)
+    }
+
+    /// Get index records with matching keys ( for a covering index, see [Table::index_offsets] ).
+    pub fn scan_cover(self: &Rc<Table>, db: &DB, keys: Vec<Value>, index: usize) -> IndexScan {
+        self.scan_index(db, keys, index, true, false)
+    }
+
+    /// Get records ( or index records if cover is true ) with matching keys, in index order or in reverse order.
+    pub fn scan_index(
+        self: &Rc<Table>,
+        db: &DB,
+        keys: Vec<Value>,
+        index: usize,
+        cover: bool,
+        rev: bool,
+    ) -> IndexScan {
         let ixlist = &*self.ixlist.borrow();
         let ix = &ixlist[index];
-        let ikey = IndexKey::new(self, ix.cols.clone(), keys.clone(), Ordering::Less);
-        let ixa = ix.file.asc(db, Box::new(ikey));
+        let ixa: DataSource = if rev {
+            let ikey = IndexKey::new(ix, keys.clone(), Ordering::Greater);
+            Box::new(ix.file.dsc(db, Box::new(ikey)))
+        } else {
+            let ikey = IndexKey::new(ix, keys.clone(), Ordering::Less);
+            Box::new(ix.file.asc(db, Box::new(ikey)))
+        };
         IndexScan {
             ixa,
             keys,
-            cols: ix.cols.clone(),
+            typ: ix.typ.clone(),
+            pk: ix.pk.clone(),
             table: self.clone(),
             db: db.clone(),
+            cover,
         }
     }
 
     /// Add the specified index to the table.
-    pub fn add_index(&self, root: u64, cols: Vec<usize>, id: i64) {
-        let key_size = self.info.index_key_size(&cols) + 8;
-        let file = Rc::new(SortedFile::new(key_size, key_size, root));
+    pub fn add_index(&self, root: u64, cols: Vec<usize>, include: Vec<usize>, id: i64) {
+        self.add_exp_index(root, cols, include, Vec::new(), id);
+    }
+
+    /// Add the specified index to the table. Column number EXP_COL + i is the key expression exps\[i\].
+    pub fn add_exp_index(
+        &self,
+        root: u64,
+        mut cols: Vec<usize>,
+        mut include: Vec<usize>,
+        mut exps: Vec<IndexExp>,
+        id: i64,
+    ) {
         let list = &mut self.ixlist.borrow_mut();
+        // Number the expressions, so the same expression has the same column number in every index.
+        let mut next = EXP_COL + list.iter().map(|ix| ix.exps.len()).sum::<usize>();
+        // map[i] is the column number for exps[i].
+        let mut map = Vec::with_capacity(exps.len());
+        for i in 0..exps.len() {
+            let text = &exps[i].text;
+            let col = if let Some(y) = list
+                .iter()
+                .flat_map(|ix| ix.exps.iter())
+                .chain(exps[0..i].iter())
+                .find(|y| y.text == *text)
+            {
+                y.col
+            } else {
+                next += 1;
+                next - 1
+            };
+            exps[i].col = col;
+            map.push(col);
+        }
+        for c in &mut cols {
+            if *c >= EXP_COL {
+                *c = map[*c - EXP_COL];
+            }
+        }
+        let mut typ = Vec::new();
+        for c in &cols {
+            typ.push(if *c < EXP_COL {
+                self.info.typ[*c]
+            } else {
+                exps.iter().find(|x| x.col == *c).unwrap().typ
+            });
+        }
+        let key_size = typ.iter().map(|t| data_size(*t)).sum::<usize>() + 8;
+        // Records are fetched using the PRIMARY KEY ( if any ), so the key values are stored in the index.
+        for c in &self.info.key {
+            if !cols.contains(c) && !include.contains(c) {
+                include.push(*c);
+            }
+        }
+        typ.extend(include.iter().map(|c| self.info.typ[*c]));
+        let rec_size = key_size + self.info.index_key_size(&include);
+        let mut pk = vec![0; self.info.key.len()];
+        let mut off = 8;
+        for (c, t) in cols.iter().chain(include.iter()).zip(typ.iter()) {
+            if let Some(i) = self.info.key.iter().position(|k| k == c) {
+                pk[i] = off;
+            }
+            off += data_size(*t);
+        }
+        let file = Rc::new(SortedFile::new(rec_size, key_size, root));
         list.push(Index {
             file,
             cols: Rc::new(cols),
+            include: Rc::new(include),
+            typ: Rc::new(typ),
+            exps: Rc::new(exps),
+            filter: Rc::new(Vec::new()),
+            stats: None,
+            pk: Rc::new(pk),
+            build: None,
             id,
         });
     }
 
+    /// Set the WHERE conditions of the last index added ( partial index ).
+    pub fn set_filter(&self, filter: Vec<FilterCond>) {
+        let list = &mut self.ixlist.borrow_mut();
+        list.last_mut().unwrap().filter = Rc::new(filter);
+    }
+
+    /// Set the build progress of the last index added ( see [Index::build] ).
+    pub fn set_build(&self, progress: u64) {
+        let list = &mut self.ixlist.borrow_mut();
+        list.last_mut().unwrap().build = Some(progress);
+    }
+
+    /// Add up to n rows to the first index being built ONLINE.
+    /// Result is the index and the number of rows read, or None if no index is being built.
+    pub fn build_index(&self, db: &DB, n: usize) -> Option<(usize, usize)> {
+        let list = &mut *self.ixlist.borrow_mut();
+        let index = list.iter().position(|ix| ix.build.is_some())?;
+        let ix = &mut list[index];
+        let start = Id {
+            id: ix.build.unwrap(),
+        };
+        ix.build = None;
+        let mut row = self.row();
+        let mut rows = 0;
+        for (pp, off) in self.file.asc(db, Box::new(start)) {
+            let p = pp.borrow();
+            let data = &p.data[off..];
+            if rows == n {
+                ix.build = Some(util::getu64(data, 0));
+                break;
+            }
+            rows += 1;
+            row.load(db, data);
+            if ix.includes(&self.info, &row) {
+                let mut ixr = IndexRow::new(db, self, ix, &row);
+                ixr.encode_exps(db, ix);
+                ix.file.insert(db, &ixr);
+            }
+        }
+        Some((index, rows))
+    }
+
+    /// Set the statistics of an index.
+    pub fn set_stats(&self, index: usize, stats: IndexStats) {
+        self.ixlist.borrow_mut()[index].stats = Some(Rc::new(stats));
+    }
+
+    /// Collect statistics for each index ( see [IndexStats] ).
+    pub fn analyze(&self, db: &DB) -> Vec<IndexStats> {
+        let rows = self.scan(db).count() as u64;
+        let mut result = Vec::new();
+        for ix in &*self.ixlist.borrow() {
+            let n = ix.cols.len();
+            let mut records = 0;
+            let mut distinct = vec![0; n];
+            let mut prev: Vec<Value> = Vec::new();
+            for (pp, off) in ix.file.asc(db, Box::new(Zero {})) {
+                let p = pp.borrow();
+                let data = &p.data[off..];
+                let mut keys = Vec::with_capacity(n);
+                let mut off = 8;
+                for typ in &ix.typ[0..n] {
+                    keys.push(Value::load(db, *typ, data, off).0);
+                    off += data_size(*typ);
+                }
+                // Find the first key value that differs from the previous record.
+                let pos = if prev.is_empty() {
+                    0
+                } else {
+                    (0..n)
+                        .find(|i| keys[*i].collate_cmp(&prev[*i], ix.typ[*i]) != Ordering::Equal)
+                        .unwrap_or(n)
+                };
+                for d in &mut distinct[pos..] {
+                    *d += 1;
+                }
+                records += 1;
+                prev = keys;
+            }
+            result.push(IndexStats::new(rows, records, distinct));
+        }
+        result
+    }
+
+    /// Set which key columns of the last index added are in descending order.
+    pub fn set_desc(&self, desc: &[bool]) {
+        let list = &mut self.ixlist.borrow_mut();
+        let ix = list.last_mut().unwrap();
+        let mut typ = (*ix.typ).clone();
+        for (t, d) in typ.iter_mut().zip(desc) {
+            if *d {
+                *t |= DESC;
+            }
+        }
+        ix.typ = Rc::new(typ);
+    }
+
+    /// Get the offsets of columns in the records of an index ( key columns then INCLUDE columns ).
+    /// Result is None if a column in cols is not stored in the index.
+    pub fn index_offsets(&self, index: usize, cols: &[usize]) -> Option<Vec<usize>> {
+        let list = self.ixlist.borrow();
+        let ix = &list[index];
+        let mut offsets = vec![usize::MAX; self.info.typ.len()];
+        let mut off = 8;
+        for (c, typ) in ix.cols.iter().chain(ix.include.iter()).zip(ix.typ.iter()) {
+            if *c < EXP_COL {
+                offsets[*c] = off;
+            }
+            off += data_size(*typ);
+        }
+        for c in cols {
+            if *c != usize::MAX && offsets[*c] == usize::MAX {
+                return None;
+            }
+        }
+        Some(offsets)
+    }
+
     /// Delete the specified index.
     pub fn delete_index(&self, db: &DB, ix: usize) {
         let ixlist = &*self.ixlist.borrow();
         let ix = &ixlist[ix];
+        self.free_exp_codes(db, ix);
         let row = self.row();
-        let ixr = IndexRow::new(self, ix.cols.clone(), &row);
+        let ixr = IndexRow::new(db, self, ix, &row);
         ix.file.free_pages(db, &ixr);
     }
 
@@ -242,8 +845,11 @@ impl Table {
         for (pp, off) in self.scan(db) {
             let p = pp.borrow();
             row.load(db, &p.data[off..]);
-            let ixr = IndexRow::new(self, ix.cols.clone(), &row);
-            ix.file.insert(db, &ixr);
+            if ix.includes(&self.info, &row) {
+                let mut ixr = IndexRow::new(db, self, ix, &row);
+                ixr.encode_exps(db, ix);
+                ix.file.insert(db, &ixr);
+            }
         }
     }
 
@@ -296,17 +902,17 @@ impl Table {
     }
 
     #[cfg(feature = "pack")]
-    /// Repack the file pages.
-    pub fn repack(&self, db: &DB, k: usize) -> i64 {
+    /// Repack the file pages ( k = 0 for the table, otherwise index k-1 ), stopping once a limit of the budget is reached.
+    pub fn repack(&self, db: &DB, k: usize, b: &mut sortedfile::RepackBudget) -> i64 {
         let row = self.row();
         if k == 0 {
-            self.file.repack(db, &row)
+            self.file.repack(db, &row, b)
         } else {
             let list = &*self.ixlist.borrow();
             if k <= list.len() {
                 let ix = &list[k - 1];
-                let ixr = IndexRow::new(self, ix.cols.clone(), &row);
-                ix.file.repack(db, &ixr)
+                let ixr = IndexRow::new(db, self, ix, &row);
+                ix.file.repack(db, &ixr, b)
             } else {
                 -1
             }
@@ -360,7 +966,12 @@ impl<'d, 'i> Access<'d, 'i> {
         debug_assert!(data_kind(self.info.typ[colnum]) == DataKind::Binary);
         let off = self.info.off[colnum];
         let size = self.info.siz(colnum);
-        get_bytes(db, &self.data[off..], size).0
+        let bytes = get_bytes(db, &self.data[off..], size).0;
+        if self.info.typ[colnum] & COMPRESSED != 0 {
+            compress::decompress(&bytes)
+        } else {
+            bytes
+        }
     }
 
     /// Extract Id from byte data.
@@ -422,6 +1033,9 @@ pub struct ColInfo {
     pub off: Vec<usize>,
     /// Total data size, including Id.
     pub total: usize,
+    /// PRIMARY KEY columns ( empty if the table has no PRIMARY KEY, in which case rows are ordered by Id ).
+    /// Rows of a table with a PRIMARY KEY are located by key, finding a row by Id requires a scan.
+    pub key: Vec<usize>,
 }
 
 impl ColInfo {
@@ -434,6 +1048,7 @@ impl ColInfo {
             colnames: Vec::new(),
             off: Vec::new(),
             total: 8,
+            key: Vec::new(),
         }
     }
 
@@ -458,9 +1073,30 @@ impl ColInfo {
         self.total += size;
         self.colnames.push(name.clone());
         self.colmap.insert(name, cn);
+        if typ >> KEY_SHIFT != 0 {
+            self.key.push(cn);
+            self.key.sort_by_key(|c| self.typ[*c] >> KEY_SHIFT);
+        }
         false
     }
 
+    /// Set the PRIMARY KEY columns. The key position is recorded in the column type.
+    pub(crate) fn set_key(&mut self, key: Vec<usize>) {
+        for (i, c) in key.iter().enumerate() {
+            self.typ[*c] |= (i + 1) << KEY_SHIFT;
+        }
+        self.key = key;
+    }
+
+    /// Size of the leading part of a record that holds the Id and the PRIMARY KEY columns.
+    fn key_size(&self) -> usize {
+        self.key
+            .iter()
+            .map(|c| self.off[*c] + self.siz(*c))
+            .max()
+            .unwrap_or(8)
+    }
+
     pub(crate) fn add_altered(&mut self, ci: &ColInfo, cnum: usize, actions: &[AlterCol]) -> bool {
         let cname = &ci.colnames[cnum];
         let mut typ = ci.typ[cnum];
@@ -476,7 +1112,7 @@ impl ColInfo {
                         if data_kind(typ) != data_kind(*dt) {
                             panic!("Cannot change column data kind");
                         }
-                        typ = *dt;
+                        typ = *dt | (typ >> KEY_SHIFT << KEY_SHIFT);
                     }
                 }
                 _ => {}
@@ -516,6 +1152,17 @@ pub struct IndexInfo {
     pub iname: String,
     /// Index columns.
     pub cols: Vec<usize>,
+    /// INCLUDE columns.
+    pub include: Vec<usize>,
+    /// WHERE condition ( source SQL ) of a partial index.
+    pub filter: Option<String>,
+    /// Key expressions ( source SQL ), index column EXP_COL + i is exps\[i\].
+    pub exps: Vec<String>,
+    /// Which index columns are in descending order.
+    pub desc: Vec<bool>,
+    /// Index is built ONLINE, in steps ( see [Table::build_index] ).
+    /// Steps are run each time the database is saved ( see [crate::Database::set_build_rows] ), or by calling BUILDINDEX.
+    pub online: bool,
 }
 
 /// Row of Values, with type information.
@@ -530,6 +1177,8 @@ pub struct Row {
     pub info: Rc<ColInfo>,
     /// Codes for variable length ( binary, string ) values.
     pub codes: Vec<Code>,
+    /// Stored values of COMPRESSED columns ( calculated by encode ).
+    compressed: Vec<(usize, Value)>,
 }
 
 impl Row {
@@ -541,6 +1190,7 @@ impl Row {
             values: Vec::with_capacity(n),
             info,
             codes: Vec::with_capacity(n),
+            compressed: Vec::new(),
         };
         for t in &result.info.typ {
             result.values.push(Value::default(*t));
@@ -551,9 +1201,18 @@ impl Row {
     /// Calculate codes for current row values.
     pub fn encode(&mut self, db: &DB) {
         self.codes.clear();
+        self.compressed.clear();
         for (i, val) in self.values.iter().enumerate() {
-            let size = data_size(self.info.typ[i]);
-            let u = db.encode(val, size);
+            let typ = self.info.typ[i];
+            let size = data_size(typ);
+            let u = if typ & COMPRESSED != 0 {
+                let z = Value::RcBinary(Rc::new(compress::compress(&val.bin())));
+                let u = db.encode(&z, size);
+                self.compressed.push((i, z));
+                u
+            } else {
+                db.encode(val, size)
+            };
             self.codes.push(u);
         }
     }
@@ -588,54 +1247,174 @@ impl Record for Row {
         let t = &self.info;
         let mut off = 8;
         for (i, typ) in t.typ.iter().enumerate() {
-            self.values[i].save(*typ, data, off, self.codes[i]);
+            if typ & COMPRESSED == 0 {
+                self.values[i].save(*typ, data, off, self.codes[i]);
+            }
             off += data_size(*typ);
         }
+        for (i, z) in &self.compressed {
+            z.save(t.typ[*i], data, t.off[*i], self.codes[*i]);
+        }
     }
 
-    fn compare(&self, _db: &DB, data: &[u8]) -> Ordering {
-        let id = util::getu64(data, 0) as i64;
-        self.id.cmp(&id)
+    fn compare(&self, db: &DB, data: &[u8]) -> Ordering {
+        let info = &self.info;
+        if info.key.is_empty() {
+            let id = util::getu64(data, 0) as i64;
+            return self.id.cmp(&id);
+        }
+        for c in &info.key {
+            let typ = info.typ[*c];
+            let val = Value::load(db, typ, data, info.off[*c]).0;
+            let cf = self.values[*c].collate_cmp(&val, typ);
+            if cf != Ordering::Equal {
+                return cf;
+            }
+        }
+        Ordering::Equal
+    }
+
+    fn key(&self, db: &DB, data: &[u8]) -> Box<dyn Record> {
+        if self.info.key.is_empty() {
+            return Box::new(Id {
+                id: util::getu64(data, 0),
+            });
+        }
+        let mut result = Box::new(TableKey::new(&self.info, Vec::new(), Ordering::Equal));
+        result.load(db, data);
+        result
+    }
+
+    fn drop_key(&self, db: &DB, data: &[u8]) {
+        let info = &self.info;
+        for c in &info.key {
+            let code = Value::load(db, info.typ[*c], data, info.off[*c]).1;
+            if code.id != u64::MAX {
+                db.delcode(code);
+            }
+        }
+    }
+}
+
+/// PRIMARY KEY values, for locating records of a table with a PRIMARY KEY ( also stored in parent pages ).
+struct TableKey {
+    info: Rc<ColInfo>,
+    /// Key values ( may be a prefix of the PRIMARY KEY ).
+    key: Vec<Value>,
+    /// Codes, only computed when the key is stored in a parent page.
+    codes: Vec<Code>,
+    /// Ordering used if key values compare equal.
+    def: Ordering,
+}
+
+impl TableKey {
+    fn new(info: &Rc<ColInfo>, key: Vec<Value>, def: Ordering) -> Self {
+        Self {
+            info: info.clone(),
+            key,
+            codes: Vec::new(),
+            def,
+        }
+    }
+
+    // Load key values from data ( note: new codes are computed, as old codes may be deleted, see IndexRow::load ).
+    fn load(&mut self, db: &DB, data: &[u8]) {
+        let info = &self.info;
+        for c in &info.key {
+            let typ = info.typ[*c];
+            let val = Value::load(db, typ, data, info.off[*c]).0;
+            self.codes.push(db.encode(&val, data_size(typ)));
+            self.key.push(val);
+        }
+    }
+}
+
+impl Record for TableKey {
+    fn compare(&self, db: &DB, data: &[u8]) -> Ordering {
+        let info = &self.info;
+        for (c, k) in info.key.iter().zip(&self.key) {
+            let typ = info.typ[*c];
+            let val = Value::load(db, typ, data, info.off[*c]).0;
+            let cf = k.collate_cmp(&val, typ);
+            if cf != Ordering::Equal {
+                return cf;
+            }
+        }
+        self.def
+    }
+
+    fn save(&self, data: &mut [u8]) {
+        let info = &self.info;
+        for (i, c) in info.key.iter().enumerate() {
+            self.key[i].save(info.typ[*c], data, info.off[*c], self.codes[i]);
+        }
     }
 }
 
 /// Row for inserting into an index.
 pub struct IndexRow {
-    tinfo: Rc<ColInfo>,
-    cols: Rc<Vec<usize>>,
+    /// Types of the key columns followed by the INCLUDE columns.
+    typ: Rc<Vec<DataType>>,
+    /// Number of key columns.
+    nkey: usize,
     keys: Vec<Value>,
     codes: Vec<Code>,
     rowid: i64,
 }
 
 impl IndexRow {
-    // Construct IndexRow from Row.
-    fn new(table: &Table, cols: Rc<Vec<usize>>, row: &Row) -> Self {
-        let n = cols.len();
+    // Construct IndexRow from Row. keys holds the values of the key columns followed by the INCLUDE columns.
+    fn new(db: &DB, table: &Table, ix: &Index, row: &Row) -> Self {
+        let n = ix.typ.len();
         let mut keys = Vec::with_capacity(n);
         let mut codes = Vec::with_capacity(n);
         if !row.codes.is_empty() {
-            for c in &*cols {
-                keys.push(row.values[*c].clone());
-                codes.push(row.codes[*c]);
+            // Key expressions are evaluated for the saved row.
+            let mut data = Vec::new();
+            let mut tr = DummyTransaction {};
+            let mut ee = None;
+            if !ix.exps.is_empty() {
+                data.resize(table.info.total, 0);
+                row.save(&mut data);
+                ee = Some(EvalEnv::new(db.clone(), &mut tr));
+            }
+            for c in ix.cols.iter().chain(ix.include.iter()) {
+                if *c < EXP_COL {
+                    keys.push(row.values[*c].clone());
+                    codes.push(row.codes[*c]);
+                } else {
+                    let ee = ee.as_mut().unwrap();
+                    keys.push(ix.exp(*c).exp.eval(ee, &data));
+                    codes.push(Code {
+                        id: u64::MAX,
+                        ft: 0,
+                    });
+                }
             }
         }
         Self {
-            tinfo: table.info.clone(),
-            cols,
+            typ: ix.typ.clone(),
+            nkey: ix.cols.len(),
             rowid: row.id,
             keys,
             codes,
         }
     }
 
+    // Compute codes for key expression values ( before inserting into the index ).
+    fn encode_exps(&mut self, db: &DB, ix: &Index) {
+        for (pos, typ) in ix.coded_exps() {
+            self.codes[pos] = db.encode(&self.keys[pos], data_size(typ));
+        }
+    }
+
     // Load IndexRow from data ( note: new codes are computed, as old codes may be deleted ).
     // Since it's unusual for long strings to be keys, code computation should be rare.
     fn load(&mut self, db: &DB, data: &[u8]) {
         self.rowid = util::getu64(data, 0) as i64;
         let mut off = 8;
-        for col in &*self.cols {
-            let typ = self.tinfo.typ[*col];
+        for typ in &self.typ[0..self.nkey] {
+            let typ = *typ;
             let val = Value::load(db, typ, data, off).0;
             let size = data_size(typ);
             let code = db.encode(&val, size);
@@ -651,16 +1430,16 @@ impl Record for IndexRow {
         let mut ix = 0;
         let mut off = 8;
         loop {
-            let typ = self.tinfo.typ[self.cols[ix]];
+            let typ = self.typ[ix];
             // Could have special purpose Value method which compares instead of loading to save heap allocations.
             let val = Value::load(db, typ, data, off).0;
-            let cf = val.cmp(&self.keys[ix]);
+            let cf = key_cmp(&val, &self.keys[ix], typ);
             if cf != Ordering::Equal {
                 return cf;
             }
             ix += 1;
             off += data_size(typ);
-            if ix == self.cols.len() {
+            if ix == self.nkey {
                 let rowid = util::getu64(data, 0) as i64;
                 return self.rowid.cmp(&rowid);
             }
@@ -670,18 +1449,18 @@ impl Record for IndexRow {
     fn save(&self, data: &mut [u8]) {
         util::setu64(data, self.rowid as u64);
         let mut off = 8;
-        for (ix, k) in self.keys.iter().enumerate() {
-            let typ = self.tinfo.typ[self.cols[ix]];
-            k.save(typ, data, off, self.codes[ix]);
-            off += data_size(typ);
+        // Keys loaded by key() do not include the INCLUDE column values.
+        for (ix, typ) in self.typ.iter().take(self.keys.len()).enumerate() {
+            self.keys[ix].save(*typ, data, off, self.codes[ix]);
+            off += data_size(*typ);
         }
     }
 
     fn key(&self, db: &DB, data: &[u8]) -> Box<dyn Record> {
-        let n = self.cols.len();
+        let n = self.nkey;
         let mut result = Box::new(IndexRow {
-            cols: self.cols.clone(),
-            tinfo: self.tinfo.clone(),
+            typ: self.typ.clone(),
+            nkey: n,
             rowid: 0,
             keys: Vec::with_capacity(n),
             codes: Vec::with_capacity(n),
@@ -692,23 +1471,30 @@ impl Record for IndexRow {
 
     fn drop_key(&self, db: &DB, data: &[u8]) {
         let mut off = 8;
-        for col in &*self.cols {
-            let typ = self.tinfo.typ[*col];
-            let code = Value::load(db, typ, data, off).1;
+        for typ in &self.typ[0..self.nkey] {
+            let code = Value::load(db, *typ, data, off).1;
             if code.id != u64::MAX {
                 db.delcode(code);
             }
-            off += data_size(typ);
+            off += data_size(*typ);
         }
     }
 }
 
+/// Compare a stored index value with a key value.
+fn key_cmp(val: &Value, key: &Value, typ: DataType) -> Ordering {
+    let cf = val.collate_cmp(key, typ);
+    if typ & DESC != 0 {
+        cf.reverse()
+    } else {
+        cf
+    }
+}
+
 /// Key for searching index.
 struct IndexKey {
-    /// Information about the indexed table.
-    tinfo: Rc<ColInfo>,
-    /// List of indexed columns.
-    cols: Rc<Vec<usize>>,
+    /// Types of the index columns.
+    typ: Rc<Vec<DataType>>,
     /// Key values.
     key: Vec<Value>,
     /// Ordering used if keys compare equal.
@@ -716,11 +1502,10 @@ struct IndexKey {
 }
 
 impl IndexKey {
-    fn new(table: &Table, cols: Rc<Vec<usize>>, key: Vec<Value>, def: Ordering) -> Self {
+    fn new(ix: &Index, key: Vec<Value>, def: Ordering) -> Self {
         Self {
-            tinfo: table.info.clone(),
+            typ: ix.typ.clone(),
             key,
-            cols,
             def,
         }
     }
@@ -734,9 +1519,9 @@ impl Record for IndexKey {
             if ix == self.key.len() {
                 return self.def;
             }
-            let typ = self.tinfo.typ[self.cols[ix]];
+            let typ = self.typ[ix];
             let val = Value::load(db, typ, data, off).0;
-            let cf = val.cmp(&self.key[ix]);
+            let cf = key_cmp(&val, &self.key[ix], typ);
             if cf != Ordering::Equal {
                 return cf;
             }
@@ -748,20 +1533,24 @@ impl Record for IndexKey {
 
 /// State for fetching records using an index.
 pub struct IndexScan {
-    ixa: Asc,
+    ixa: DataSource,
     table: Rc<Table>,
     db: DB,
-    cols: Rc<Vec<usize>>,
+    typ: Rc<Vec<DataType>>,
     keys: Vec<Value>,
+    /// Offsets of the PRIMARY KEY values ( see [Index::pk] ).
+    pk: Rc<Vec<usize>>,
+    /// Yield the index records rather than the table records.
+    cover: bool,
 }
 
 impl IndexScan {
     fn keys_equal(&self, data: &[u8]) -> bool {
         let mut off = 8;
         for (ix, k) in self.keys.iter().enumerate() {
-            let typ = self.table.info.typ[self.cols[ix]];
+            let typ = self.typ[ix];
             let val = Value::load(&self.db, typ, data, off).0;
-            let cf = val.cmp(k);
+            let cf = val.collate_cmp(k, typ);
             if cf != Ordering::Equal {
                 return false;
             }
@@ -781,13 +1570,44 @@ impl Iterator for IndexScan {
             if !self.keys_equal(data) {
                 return None;
             }
-            let id = util::getu64(data, 0);
-            return self.table.id_get(&self.db, id);
+            if self.cover {
+                drop(p);
+                return Some((pp, off));
+            }
+            return self.table.ix_fetch(&self.db, &self.pk, data);
         }
         None
     }
 }
 
+/// State for fetching records of a table with a PRIMARY KEY with matching key values.
+pub struct KeyScan {
+    asc: Asc,
+    info: Rc<ColInfo>,
+    db: DB,
+    keys: Vec<Value>,
+}
+
+impl Iterator for KeyScan {
+    type Item = (PagePtr, usize);
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        let (pp, off) = self.asc.next()?;
+        {
+            let p = pp.borrow();
+            let info = &self.info;
+            for (c, k) in info.key.iter().zip(&self.keys) {
+                let typ = info.typ[*c];
+                let val = Value::load(&self.db, typ, &p.data, off + info.off[*c]).0;
+                if val.collate_cmp(k, typ) != Ordering::Equal {
+                    return None;
+                }
+            }
+        }
+        Some((pp, off))
+    }
+}
+
 /// State for fetching record with specified id.
 pub struct IdScan {
     id: i64,
@@ -808,17 +1628,89 @@ impl Iterator for IdScan {
     }
 }
 
+/// Get the column number of a key expression : a column, or an expression that matches an index expression.
+fn key_col(e: &Expr) -> Option<usize> {
+    if matches!(e.exp, ExprIs::ColName(_)) || e.col >= EXP_COL {
+        Some(e.col)
+    } else {
+        None
+    }
+}
+
+/// Check and compile a key expression of an index.
+pub fn index_exp(b: &Block, e: &mut Expr) -> IndexExp {
+    compile::c_check(b, e);
+    check_index_exp(e);
+    IndexExp {
+        col: EXP_COL,
+        text: explain::exp_text(b, e),
+        typ: exp_type(e.data_type),
+        exp: c_value(b, e),
+    }
+}
+
+/// Storage type for an index key expression ( expression types may have no size ).
+fn exp_type(t: DataType) -> DataType {
+    let typ = match data_kind(t) {
+        DataKind::Binary => BINARY,
+        DataKind::String => STRING,
+        DataKind::Int => INT,
+        DataKind::Float => DOUBLE,
+        DataKind::Bool => BOOL,
+        DataKind::None => panic!("index expression has no type"),
+    };
+    typ | (t & COLLATION)
+}
+
+/// Check an index key expression only uses columns, constants and deterministic builtin functions.
+fn check_index_exp(e: &Expr) {
+    match &e.exp {
+        ExprIs::Const(_) => {}
+        ExprIs::ColName(x) => {
+            if e.data_type & COMPRESSED != 0 {
+                panic!("cannot index COMPRESSED column {}", x);
+            }
+        }
+        ExprIs::Binary(_, e1, e2) => {
+            check_index_exp(e1);
+            check_index_exp(e2);
+        }
+        ExprIs::Not(x) | ExprIs::Minus(x) => check_index_exp(x),
+        ExprIs::Case(list, els) => {
+            for (w, t) in list {
+                check_index_exp(w);
+                check_index_exp(t);
+            }
+            check_index_exp(els);
+        }
+        ExprIs::BuiltinCall(name, list) => {
+            if !builtin::DETERMINISTIC.contains(&name.as_str()) {
+                panic!("index expression cannot use {}", name);
+            }
+            for x in list {
+                check_index_exp(x);
+            }
+        }
+        ExprIs::List(list) => {
+            for x in list {
+                check_index_exp(x);
+            }
+        }
+        _ => panic!("index expression can only use columns, constants and builtin functions"),
+    }
+}
+
 /// Gets the list of columns that are known from a WHERE condition.
 fn get_known_cols(we: &Expr, kc: &mut SmallSet) {
     match &we.exp {
         ExprIs::Binary(Token::Equal, e1, e2) => {
             if e2.is_constant {
-                if let ExprIs::ColName(_) = &e1.exp {
-                    kc.insert(e1.col);
+                if let Some(col) = key_col(e1) {
+                    kc.insert(col);
                 }
             } else if e1.is_constant {
-                if let ExprIs::ColName(_) = &e2.exp {
-                    kc.insert(e2.col);
+                if let Some(col) = key_col(e2) {
+                    kc.insert(col);
                 }
             }
         }
@@ -830,6 +1722,183 @@ fn get_known_cols(we: &Expr, kc: &mut SmallSet) {
     }
 }
 
+/// Get the WHERE conditions of a partial index. Each condition must compare a column with a literal constant.
+pub fn filter_conds(b: &Block, we: &mut Expr) -> Vec<FilterCond> {
+    compile::c_check(b, we);
+    if b.kind(we) != DataKind::Bool {
+        panic!("index WHERE expression must be bool")
+    }
+    let mut result = Vec::new();
+    query_conds(we, &mut result);
+    let mut conds = Vec::new();
+    conjuncts(we, &mut conds);
+    if conds.len() != result.len() {
+        panic!("index WHERE conditions must compare a column with a constant")
+    }
+    result
+}
+
+/// Get the conditions of a WHERE expression that compare a column with a literal constant.
+fn query_conds(we: &Expr, list: &mut Vec<FilterCond>) {
+    match &we.exp {
+        ExprIs::Binary(Token::And, e1, e2) => {
+            query_conds(e1, list);
+            query_conds(e2, list);
+        }
+        ExprIs::Binary(op, e1, e2) => {
+            let op = *op;
+            match (&e1.exp, &e2.exp) {
+                (ExprIs::ColName(_), ExprIs::Const(k)) if e1.col != usize::MAX => {
+                    if let Some(op) = cmp_op(op, false) {
+                        list.push((e1.col, op, k.clone()));
+                    }
+                }
+                (ExprIs::Const(k), ExprIs::ColName(_)) if e2.col != usize::MAX => {
+                    if let Some(op) = cmp_op(op, true) {
+                        list.push((e2.col, op, k.clone()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Get comparison operator, reversed if the constant is on the left.
+fn cmp_op(op: Token, reverse: bool) -> Option<Token> {
+    Some(match op {
+        Token::Equal | Token::NotEqual => op,
+        Token::Less if reverse => Token::Greater,
+        Token::LessEqual if reverse => Token::GreaterEqual,
+        Token::Greater if reverse => Token::Less,
+        Token::GreaterEqual if reverse => Token::LessEqual,
+        Token::Less | Token::LessEqual | Token::Greater | Token::GreaterEqual => op,
+        _ => return None,
+    })
+}
+
+/// Compare value v with constant k.
+fn compare(v: &Value, op: Token, k: &Value, typ: DataType) -> bool {
+    let cf = v.collate_cmp(k, typ);
+    match op {
+        Token::Equal => cf == Ordering::Equal,
+        Token::NotEqual => cf != Ordering::Equal,
+        Token::Less => cf == Ordering::Less,
+        Token::LessEqual => cf != Ordering::Greater,
+        Token::Greater => cf == Ordering::Greater,
+        Token::GreaterEqual => cf != Ordering::Less,
+        _ => panic!(),
+    }
+}
+
+/// Can a ( partial ) index be used for a query with conditions qc? An index being built ONLINE cannot be used.
+fn usable(ix: &Index, qc: &[FilterCond], info: &ColInfo) -> bool {
+    ix.build.is_none() && ix.filter.iter().all(|f| implied(f, qc, info.typ[f.0]))
+}
+
+/// Check whether reading an index gives rows in ORDER BY order, given that the first n index columns have fixed values.
+/// cols and typ are the index columns and their types. Result is whether the index is read in reverse order.
+fn order_rev(cols: &[usize], typ: &[DataType], n: usize, orderby: &[(Expr, bool)]) -> Option<bool> {
+    let mut rev = None;
+    let mut pos = n;
+    for (e, desc) in orderby {
+        let col = key_col(e)?;
+        if cols[0..n].contains(&col) {
+            continue;
+        }
+        if pos == cols.len() || cols[pos] != col {
+            return None;
+        }
+        let typ = typ[pos];
+        if e.data_type & COLLATION != typ & COLLATION {
+            return None;
+        }
+        // Index records are stored in descending order of values for ascending columns ( see key_cmp ).
+        let r = *desc == (typ & DESC != 0);
+        if *rev.get_or_insert(r) != r {
+            return None;
+        }
+        pos += 1;
+    }
+    Some(rev.unwrap_or(false))
+}
+
+/// Is the condition f implied by the list of query conditions?
+fn implied(f: &FilterCond, qc: &[FilterCond], typ: DataType) -> bool {
+    let (col, op, k) = f;
+    qc.iter().any(|(qcol, qop, qk)| {
+        qcol == col
+            && ((qop == op && qk.collate_cmp(k, typ) == Ordering::Equal)
+                || (*qop == Token::Equal && compare(qk, *op, k, typ)))
+    })
+}
+
+/// Get the conditions of a WHERE expression ( operands of AND ).
+fn conjuncts<'e>(we: &'e mut Expr, list: &mut Vec<&'e mut Expr>) {
+    if !matches!(we.exp, ExprIs::Binary(Token::And, _, _)) {
+        list.push(we);
+    } else if let ExprIs::Binary(Token::And, e1, e2) = &mut we.exp {
+        conjuncts(e1, list);
+        conjuncts(e2, list);
+    }
+}
+
+/// Get the column of a condition of the form col = x OR col = y .. or col IN ( x, y .. ), where x, y .. are constant.
+/// col = x may also be written x = col.
+pub fn key_list_col(we: &Expr) -> Option<usize> {
+    match &we.exp {
+        ExprIs::Binary(Token::Equal, e1, e2) => {
+            if e2.is_constant {
+                if let Some(col) = key_col(e1) {
+                    return Some(col);
+                }
+            } else if e1.is_constant {
+                if let Some(col) = key_col(e2) {
+                    return Some(col);
+                }
+            }
+            None
+        }
+        ExprIs::Binary(Token::In, e1, e2) => {
+            if let Some(col) = key_col(e1) {
+                let keys = if let ExprIs::List(x) = &e2.exp {
+                    x.iter().all(|x| x.is_constant)
+                } else {
+                    e2.is_constant
+                };
+                if keys {
+                    return Some(col);
+                }
+            }
+            None
+        }
+        ExprIs::Binary(Token::Or, e1, e2) => {
+            let c1 = key_list_col(e1)?;
+            let c2 = key_list_col(e2)?;
+            if c1 == c2 {
+                Some(c1)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Get the key expressions of a condition for which key_list_col is not None.
+fn key_list<'e>(we: &'e mut Expr, list: &mut Vec<&'e mut Expr>) {
+    match &mut we.exp {
+        ExprIs::Binary(Token::Equal, e1, e2) => list.push(if e2.is_constant { e2 } else { e1 }),
+        ExprIs::Binary(Token::In, _, e2) => list.extend(compile::in_list(e2).iter_mut()),
+        ExprIs::Binary(Token::Or, e1, e2) => {
+            key_list(e1, list);
+            key_list(e2, list);
+        }
+        _ => panic!(),
+    }
+}
+
 /// Count the number of index columns that are known.
 fn covered(clist: &[usize], kc: &SmallSet) -> usize {
     let mut result = 0;
@@ -852,16 +1921,16 @@ fn get_keys(
     match &mut we.exp {
         ExprIs::Binary(Token::Equal, e1, e2) => {
             if e2.is_constant {
-                if let ExprIs::ColName(_) = &e1.exp {
-                    if cols.remove(e1.col) {
-                        keys.insert(e1.col, c_value(b, e2));
+                if let Some(col) = key_col(e1) {
+                    if cols.remove(col) {
+                        keys.insert(col, c_value(b, e2));
                         return None;
                     }
                 }
             } else if e1.is_constant {
-                if let ExprIs::ColName(_) = &e2.exp {
-                    if cols.remove(e2.col) {
-                        keys.insert(e2.col, c_value(b, e1));
+                if let Some(col) = key_col(e2) {
+                    if cols.remove(col) {
+                        keys.insert(col, c_value(b, e1));
                         return None;
                     }
                 }
@@ -0,0 +1,215 @@
+use crate::*;
+
+/// Trigger event : INSERT.
+pub const INSERT: u8 = 1;
+/// Trigger event : UPDATE.
+pub const UPDATE: u8 = 2;
+/// Trigger event : DELETE.
+pub const DELETE: u8 = 4;
+
+/// Trigger, executed after rows of a table are inserted, updated or deleted.
+#[non_exhaustive]
+pub struct Trigger {
+    /// Events ( bits INSERT, UPDATE, DELETE ).
+    pub events: u8,
+    /// Compiled body, parameters are the old and new row values.
+    pub function: Rc<Function>,
+}
+
+/// Triggers for a table.
+#[non_exhaustive]
+pub struct TriggerList {
+    /// Schema generation when the triggers were loaded.
+    pub generation: u64,
+    /// Parameter names : old_Id, old_col... then new_Id, new_col...
+    pub names: Vec<String>,
+    /// Parameter types.
+    pub typ: Vec<DataType>,
+    /// The triggers.
+    pub list: Vec<Trigger>,
+}
+
+/// Name of the system table that holds trigger definitions ( created when the first trigger is created ).
+fn trigger_table_name() -> ObjRef {
+    ObjRef::new("sys", "Trigger")
+}
+
+/// Get the system Trigger table, creating it if required.
+fn trigger_table(db: &DB, create: bool) -> Option<Rc<Table>> {
+    let name = trigger_table_name();
+    if let Some(t) = db.get_table(&name) {
+        return Some(t);
+    }
+    if !create {
+        return None;
+    }
+    // Columns are Table, Name, Events, Definition.
+    let mut info = ColInfo::empty(name.clone());
+    info.add("Table".to_string(), INT);
+    info.add("Name".to_string(), NAMESTR);
+    info.add("Events".to_string(), INT);
+    info.add("Def".to_string(), BIGSTR);
+    sys::create_table(db, &info);
+    sys::create_index(
+        db,
+        &IndexInfo {
+            tname: name.clone(),
+            iname: "ByTable".to_string(),
+            cols: vec![0],
+            include: Vec::new(),
+            filter: None,
+            exps: Vec::new(),
+            desc: vec![false],
+            online: false,
+        },
+    );
+    db.get_table(&name)
+}
+
+/// Create a trigger by saving the source into the system Trigger table.
+pub fn create(db: &DB, name: &str, tname: &ObjRef, events: u8, source: Rc<String>) {
+    let table = if let Some(t) = db.get_table(tname) {
+        t
+    } else {
+        panic!("table {} not found", tname.str())
+    };
+    if get_id(db, table.id, name).is_some() {
+        panic!("trigger {} already exists", name);
+    }
+    let t = trigger_table(db, true).unwrap();
+    let mut row = t.row();
+    row.id = t.alloc_id(db);
+    row.values[0] = Value::Int(table.id);
+    row.values[1] = Value::String(Rc::new(name.to_string()));
+    row.values[2] = Value::Int(events as i64);
+    row.values[3] = Value::String(source);
+    t.insert(db, &mut row);
+    db.schema_changed();
+}
+
+/// Get the id of a trigger.
+pub fn get_id(db: &DB, table_id: i64, name: &str) -> Option<i64> {
+    let t = trigger_table(db, false)?;
+    for (pp, off) in t.scan_key(db, Value::Int(table_id), 0) {
+        let p = &pp.borrow();
+        let a = t.access(p, off);
+        if a.str(db, 1) == name {
+            return Some(a.id() as i64);
+        }
+    }
+    None
+}
+
+/// Get the parameter names and types for the triggers of a table.
+pub(crate) fn params(info: &ColInfo) -> (Vec<String>, Vec<DataType>) {
+    let mut names = Vec::new();
+    let mut typ = Vec::new();
+    for prefix in ["old_", "new_"] {
+        names.push(format!("{}Id", prefix));
+        typ.push(INT);
+        for (i, cname) in info.colnames.iter().enumerate() {
+            names.push(format!("{}{}", prefix, cname));
+            // Column attributes ( COMPRESSED, collation ) do not apply to local variables.
+            typ.push(info.typ[i] % (1 << (KBITS + SBITS)));
+        }
+    }
+    (names, typ)
+}
+
+/// Get the triggers for a table, loading them if the schema has changed since they were last loaded.
+pub fn get(db: &DB, table: &Table) -> Rc<TriggerList> {
+    if let Some(x) = &*table.triggers.borrow() {
+        if x.generation == db.schema_gen.get() {
+            return x.clone();
+        }
+    }
+    let (names, typ) = params(&table.info);
+    let mut list = Vec::new();
+    if let Some(t) = trigger_table(db, false) {
+        for (pp, off) in t.scan_key(db, Value::Int(table.id), 0) {
+            let (name, events, source) = {
+                let p = &pp.borrow();
+                let a = t.access(p, off);
+                (a.str(db, 1), a.int(2) as u8, Rc::new(a.str(db, 3)))
+            };
+            let mut p = Parser::new(&source, db);
+            p.b.parse_only = true;
+            p.parse_trigger(&names, &typ);
+            let function = Rc::new(Function {
+                compiled: Cell::new(false),
+                ilist: RefCell::new(Vec::new()),
+                lines: RefCell::new(Vec::new()),
+                name: ObjRef::new(&table.info.name.schema, &name),
+                local_typ: p.b.local_typ,
+                return_type: NONE,
+                param_count: p.b.param_count,
+                source: source.clone(),
+            });
+            list.push(Trigger { events, function });
+        }
+    }
+    let result = Rc::new(TriggerList {
+        generation: db.schema_gen.get(),
+        names,
+        typ,
+        list,
+    });
+    *table.triggers.borrow_mut() = Some(result.clone());
+    result
+}
+
+impl EvalEnv<'_> {
+    /// Execute the triggers of a table for an event. old and new are the row values before and after the event.
+    pub(crate) fn fire(&mut self, table: &Table, event: u8, old: Option<&Row>, new: Option<&Row>) {
+        let triggers = get(&self.db, table);
+        if triggers.list.is_empty() {
+            return;
+        }
+        // LASTID() is not affected by rows inserted by triggers.
+        let lastid = self.db.lastid.get();
+        for tr in triggers.list.iter().filter(|x| x.events & event != 0) {
+            compile::c_compile(
+                &self.db,
+                &tr.function,
+                Some((&triggers.names, &triggers.typ)),
+            );
+            for row in [old, new] {
+                if let Some(row) = row {
+                    self.stack.push(Value::Int(row.id));
+                    for v in &row.values {
+                        self.stack.push(v.clone());
+                    }
+                } else {
+                    for t in &triggers.typ[0..triggers.typ.len() / 2] {
+                        self.stack.push(Value::default(*t));
+                    }
+                }
+            }
+            self.call(&tr.function);
+        }
+        self.db.lastid.set(lastid);
+    }
+
+    /// Delete the triggers of a dropped table.
+    pub(crate) fn drop_triggers(&mut self, table_id: i64) {
+        if trigger_table(&self.db, false).is_some() {
+            let sql = format!("DELETE FROM sys.Trigger WHERE Table = {}", table_id);
+            self.db.run(&sql, self.tr);
+        }
+    }
+
+    /// Drop a trigger.
+    pub(crate) fn drop_trigger(&mut self, tname: &ObjRef, name: &str) {
+        let id = self
+            .db
+            .get_table(tname)
+            .and_then(|t| get_id(&self.db, t.id, name));
+        if let Some(id) = id {
+            let sql = format!("DELETE FROM sys.Trigger WHERE Id = {}", id);
+            self.db.run(&sql, self.tr);
+            self.db.schema_changed();
+        } else {
+            panic!("Drop Trigger not found {} on {}", name, tname.str());
+        }
+    }
+}
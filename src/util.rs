@@ -160,14 +160,78 @@ pub fn parse_hex(s: &[u8]) -> Vec<u8> {
 
 /// Convert bytes to hex string.
 pub fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(2 + bytes.len() * 2);
+    s.push_str("0x");
+    push_hex(&mut s, bytes);
+    s
+}
+
+/// Append hex digits for bytes to string ( no 0x prefix ).
+pub fn push_hex(s: &mut String, bytes: &[u8]) {
     const HEX: &[u8; 16] = b"0123456789abcdef";
-    let mut s = vec![b'0', b'x'];
     for b in bytes {
         let b = *b as usize;
-        s.push(HEX[b / 16]);
-        s.push(HEX[b % 16]);
+        s.push(HEX[b / 16] as char);
+        s.push(HEX[b % 16] as char);
     }
-    String::from_utf8(s).unwrap()
+}
+
+/// Encode bytes as base64, url selects the URL-safe alphabet ( without padding ).
+pub fn to_base64(bytes: &[u8], url: bool) -> String {
+    let alphabet: &[u8; 64] = if url {
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_"
+    } else {
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+    };
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut n = 0;
+        for (i, b) in chunk.iter().enumerate() {
+            n |= (*b as usize) << (16 - 8 * i);
+        }
+        for i in 0..=chunk.len() {
+            s.push(alphabet[(n >> (18 - 6 * i)) & 63] as char);
+        }
+        if !url {
+            for _ in chunk.len()..3 {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+/// Decode base64 ( standard or URL-safe alphabet, padding optional ).
+pub fn from_base64(s: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut n, mut bits) = (0u32, 0);
+    for c in s {
+        let x = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => panic!("invalid base64 character"),
+        };
+        n = ((n << 6) | x as u32) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((n >> bits) as u8);
+        }
+    }
+    result
+}
+
+/// Advance splitmix64 pseudo-random state, returning the next random number.
+pub fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
 }
 
 /// Set of usize, optimised for elements < 64. default() is empty set.
@@ -57,8 +57,11 @@ impl Value {
         let size = data_size(typ);
         let val = match data_kind(typ) {
             DataKind::Binary => {
-                let (bytes, u) = get_bytes(db, &data[off..], size);
+                let (mut bytes, u) = get_bytes(db, &data[off..], size);
                 code = u;
+                if typ & COMPRESSED != 0 {
+                    bytes = compress::decompress(&bytes);
+                }
                 Value::RcBinary(Rc::new(bytes))
             }
             DataKind::String => {
@@ -113,6 +116,18 @@ impl Value {
         }
     }
 
+    /// Get the DataKind of a Value.
+    pub fn kind(&self) -> DataKind {
+        match self {
+            Value::RcBinary(_) | Value::ArcBinary(_) => DataKind::Binary,
+            Value::String(_) => DataKind::String,
+            Value::Int(_) => DataKind::Int,
+            Value::Float(_) => DataKind::Float,
+            Value::Bool(_) => DataKind::Bool,
+            _ => DataKind::None,
+        }
+    }
+
     /// Convert a Value to a String.
     pub fn str(&self) -> Rc<String> {
         match self {
@@ -189,6 +204,34 @@ impl Value {
         }
     }
 
+    /// Compare values using the collation attributes of typ ( NOCASE or CASEFOLD ).
+    pub fn collate_cmp(&self, other: &Value, typ: DataType) -> Ordering {
+        if let (Value::String(s1), Value::String(s2)) = (self, other) {
+            if typ & NOCASE != 0 {
+                let f = |c: u8| c.to_ascii_lowercase();
+                return s1.bytes().map(f).cmp(s2.bytes().map(f));
+            } else if typ & CASEFOLD != 0 {
+                return s1
+                    .chars()
+                    .flat_map(char::to_lowercase)
+                    .cmp(s2.chars().flat_map(char::to_lowercase));
+            }
+        }
+        self.cmp(other)
+    }
+
+    /// Get the sort key of a value for the collation attributes of typ.
+    pub fn collate(&self, typ: DataType) -> Value {
+        match self {
+            Value::String(s) if typ & NOCASE != 0 => Value::String(Rc::new(s.to_ascii_lowercase())),
+            Value::String(s) if typ & CASEFOLD != 0 => {
+                // Per-char lower case as in collate_cmp ( str::to_lowercase differs for final sigma ).
+                Value::String(Rc::new(s.chars().flat_map(char::to_lowercase).collect()))
+            }
+            _ => self.clone(),
+        }
+    }
+
     /// Borrow address of Binary value.
     pub fn bina(&self) -> &[u8] {
         match self {